@@ -1,7 +1,12 @@
-use std::convert::TryInto;
+use std::{
+    array::TryFromSliceError,
+    convert::{TryFrom, TryInto},
+    fmt,
+    net::SocketAddr,
+};
 
-use bytes::Bytes;
-use crypto::{blake3, Minisketch as MinisketchCrypto, MinisketchError};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use crypto::{blake3, Keypair, Minisketch as MinisketchCrypto, MinisketchError};
 
 /*
 Network messages
@@ -16,13 +21,158 @@ impl Minisketch {
         ms.deserialize(&self.0[..radius * 8]);
         Ok(ms)
     }
+
+    /// The number of differences the sketch can decode.
+    pub fn capacity(&self) -> usize {
+        self.0.len() / 8
+    }
+
+    /// Reduce the capacity of the sketch.
+    ///
+    /// A serialized sketch is a prefix of every serialized sketch of the same set with greater capacity.
+    pub fn truncate(mut self, capacity: usize) -> Self {
+        self.0.truncate(capacity * 8);
+        self
+    }
 }
 
+/// The first message sent by each side of a connection.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hello {
+    pub version: u32,
+    /// Supported feature bits.
+    pub features: u64,
+    pub pubkey: Bytes,
+    /// The address the node accepts connections on.
+    pub addr: SocketAddr,
+    pub radius: u32,
+    /// A random challenge, fresh for each connection, which the peer signs during the handshake.
+    pub nonce: u64,
+}
+
+const HANDSHAKE_DOMAIN: &[u8] = b"cauchy-handshake";
+
+impl Hello {
+    /// The handshake transcript signed by the sender of `self`, whose peer sent `peer_hello`.
+    ///
+    /// This covers both nonces and both public keys, ordered by role so it cannot be reflected back.
+    fn transcript(&self, peer_hello: &Hello) -> Vec<u8> {
+        [
+            HANDSHAKE_DOMAIN,
+            &peer_hello.nonce.to_be_bytes(),
+            &self.nonce.to_be_bytes(),
+            &peer_hello.pubkey,
+            &self.pubkey,
+        ]
+        .concat()
+    }
+
+    /// Sign the handshake transcript using the node keypair, after receiving `peer_hello`.
+    pub fn sign_transcript(&self, peer_hello: &Hello, keypair: &Keypair) -> Bytes {
+        let signature = crypto::sign(keypair, &self.transcript(peer_hello));
+        Bytes::copy_from_slice(&signature)
+    }
+
+    /// Verify the peers signature of the handshake transcript, against the public key in its `Hello`.
+    pub fn verify_transcript(&self, peer_hello: &Hello, signature: &[u8]) -> bool {
+        crypto::verify(&peer_hello.pubkey, &peer_hello.transcript(self), signature)
+    }
+}
+
+/// A numbered consensus round.
+pub type RoundId = u64;
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Status {
     pub oddsketch: Bytes,
     pub root: Bytes,
     pub nonce: u64,
+    /// The round the status was produced for.
+    pub round: RoundId,
+    /// Signature over the remaining fields by the senders node key.
+    pub signature: Bytes,
+}
+
+const STATUS_DOMAIN: &[u8] = b"cauchy-status";
+
+impl Status {
+    /// The message covered by the signature.
+    fn signing_message(&self) -> Vec<u8> {
+        [
+            STATUS_DOMAIN,
+            &self.oddsketch,
+            &self.root,
+            &self.nonce.to_be_bytes(),
+            &self.round.to_be_bytes(),
+        ]
+        .concat()
+    }
+
+    /// Sign the status using the node keypair.
+    pub fn sign(&mut self, keypair: &Keypair) {
+        let signature = crypto::sign(keypair, &self.signing_message());
+        self.signature = Bytes::copy_from_slice(&signature);
+    }
+
+    /// Verify the signature against a node public key.
+    pub fn verify(&self, pubkey: &[u8]) -> bool {
+        crypto::verify(pubkey, &self.signing_message(), &self.signature)
+    }
+}
+
+pub const TX_ID_LEN: usize = blake3::OUT_LEN;
+
+/// A transaction ID, the hash of the canonical transaction encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TxId(pub [u8; TX_ID_LEN]);
+
+impl TxId {
+    /// The short ID, used in sketches.
+    pub fn short_id(&self) -> ShortId {
+        let arr: [u8; 8] = self.0[..8].try_into().unwrap(); // This is safe
+        ShortId(u64::from_be_bytes(arr))
+    }
+}
+
+impl AsRef<[u8]> for TxId {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl TryFrom<&[u8]> for TxId {
+    type Error = TryFromSliceError;
+
+    fn try_from(raw: &[u8]) -> Result<Self, Self::Error> {
+        raw.try_into().map(Self)
+    }
+}
+
+/// A short transaction ID, the first 8 bytes of the `TxId`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ShortId(pub u64);
+
+/// The current version of the canonical transaction encoding.
+pub const TX_VERSION: u8 = 0;
+
+/// An error encountered while decoding a transaction.
+#[derive(Debug, PartialEq)]
+pub enum TransactionDecodeError {
+    UnsupportedVersion(u8),
+    Truncated,
+    TrailingBytes,
+}
+
+impl fmt::Display for TransactionDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedVersion(version) => {
+                writeln!(f, "unsupported transaction version {}", version)
+            }
+            Self::Truncated => writeln!(f, "truncated transaction"),
+            Self::TrailingBytes => writeln!(f, "trailing bytes after transaction"),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -33,19 +183,72 @@ pub struct Transaction {
 }
 
 impl Transaction {
-    pub fn serialize(self) -> Vec<u8> {
-        let mut raw = self.timestamp.to_be_bytes().to_vec();
-        raw.extend(self.binary);
-        raw.to_vec()
+    /// The length of the canonical encoding.
+    pub fn encoded_len(&self) -> usize {
+        1 + 8 + 4 + self.binary.len() + 4 + self.aux_data.len()
     }
 
-    pub fn get_id(&self) -> [u8; blake3::OUT_LEN] {
-        blake3::hash(&self.clone().serialize()).into()
+    /// Encode the transaction canonically.
+    ///
+    /// Layout: `version (u8) || timestamp (u64) || binary len (u32) || binary || aux len (u32) || aux_data`.
+    /// This encoding is hashed for the `TxId`, sent over the wire and stored.
+    pub fn encode(&self, dst: &mut BytesMut) {
+        dst.reserve(self.encoded_len());
+        dst.put_u8(TX_VERSION);
+        dst.put_u64(self.timestamp);
+        dst.put_u32(self.binary.len() as u32);
+        dst.put_slice(&self.binary);
+        dst.put_u32(self.aux_data.len() as u32);
+        dst.put_slice(&self.aux_data);
     }
 
-    pub fn get_short_id(&self) -> u64 {
-        let arr: [u8; 8] = self.get_id()[..8].try_into().unwrap();
-        u64::from_be_bytes(arr)
+    /// Decode a transaction, which must span all of `raw`.
+    pub fn decode(mut raw: Bytes) -> Result<Self, TransactionDecodeError> {
+        if raw.remaining() < 1 + 8 + 4 {
+            return Err(TransactionDecodeError::Truncated);
+        }
+        let version = raw.get_u8();
+        if version != TX_VERSION {
+            return Err(TransactionDecodeError::UnsupportedVersion(version));
+        }
+        let timestamp = raw.get_u64();
+        let binary_len = raw.get_u32() as usize;
+        if raw.remaining() < binary_len + 4 {
+            return Err(TransactionDecodeError::Truncated);
+        }
+        let binary = raw.split_to(binary_len);
+        let aux_len = raw.get_u32() as usize;
+        match raw.remaining() {
+            len if len < aux_len => Err(TransactionDecodeError::Truncated),
+            len if len > aux_len => Err(TransactionDecodeError::TrailingBytes),
+            _ => Ok(Self {
+                timestamp,
+                binary,
+                aux_data: raw,
+            }),
+        }
+    }
+
+    pub fn get_id(&self) -> TxId {
+        let mut raw = BytesMut::new();
+        self.encode(&mut raw);
+        TxId(blake3::hash(&raw).into())
+    }
+
+    pub fn get_short_id(&self) -> ShortId {
+        self.get_id().short_id()
+    }
+
+    /// The contract a message is addressed to, along with the message.
+    ///
+    /// A transaction with an empty binary is a message, whose auxiliary data is the ID of the deploying transaction
+    /// followed by the message. This is `None` for deployments.
+    pub fn message(&self) -> Option<(TxId, Bytes)> {
+        if !self.binary.is_empty() || self.aux_data.len() < TX_ID_LEN {
+            return None;
+        }
+        let contract_id = TxId::try_from(&self.aux_data[..TX_ID_LEN]).unwrap(); // This is safe
+        Some((contract_id, self.aux_data.slice(TX_ID_LEN..)))
     }
 }
 
@@ -56,5 +259,13 @@ pub struct Transactions {
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct TransactionInv {
-    pub tx_ids: Vec<Bytes>,
+    pub tx_ids: Vec<TxId>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReconcileResponse {
+    /// IDs of transactions the requester is missing.
+    pub tx_ids: Vec<TxId>,
+    /// Short IDs of transactions the responder is missing.
+    pub missing: Vec<ShortId>,
 }
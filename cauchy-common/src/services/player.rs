@@ -1,9 +1,10 @@
-use std::{fmt, net::SocketAddr, time::SystemTime};
+use std::{collections::HashSet, fmt, net::SocketAddr, time::SystemTime};
 
+use bytes::Bytes;
 use tokio::net::TcpStream;
 
 use super::{arena::InsertPeerError, vm::VMSpawnError};
-use crate::network::Minisketch;
+use crate::network::{Minisketch, ShortId, Transaction, TxId};
 
 /// Error representing missing status.
 #[derive(Debug)]
@@ -34,12 +35,40 @@ impl fmt::Display for PollStatusError {
 }
 
 /// A reconciliation request, sent to a `PeerClient`. This initiates the reconciliation round-trip.
-pub struct Reconcile(pub Minisketch);
+///
+/// Transactions whose IDs are in the set are already held, and are not fetched.
+pub struct Reconcile(pub Minisketch, pub HashSet<TxId>);
+
+/// The outcome of a reconciliation round-trip.
+pub struct Reconciliation {
+    /// Transactions fetched from the peer.
+    pub txs: Vec<Transaction>,
+    /// Short IDs of transactions the peer is missing.
+    ///
+    /// This is empty if the peer fell back to sending its full inventory.
+    pub missing: Vec<ShortId>,
+    /// The peers full inventory, if it fell back to sending it.
+    pub inventory: Option<Vec<TxId>>,
+}
+
+/// A request, sent to the `Player`, for the IDs of all transactions in the mempool.
+pub struct GetInventory;
+
+/// A request, sent to the `Player`, to split short IDs into those present in the mempool and those missing from it.
+pub struct ResolveShortIds(pub Vec<ShortId>);
 
 /// A players or peers metadata.
 pub struct Metadata {
     pub start_time: SystemTime,
     pub addr: SocketAddr,
+    pub pubkey: Bytes,
+    /// The address the node accepts connections on.
+    pub listen_addr: SocketAddr,
+    pub radius: usize,
+    /// The Noise static key, authenticated during the handshake.
+    ///
+    /// This is `None` if the connection is unencrypted.
+    pub static_key: Option<Bytes>,
 }
 
 /// A metadata request, sent to the `Player` or a `PeerClient`.
@@ -49,19 +78,118 @@ pub struct GetMetadata;
 /// An `Arena` request, sent to the `Player`. Wraps an `Arena` request.
 pub struct ArenaQuery<T>(pub T);
 
+/// The side of a connection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    /// We opened the connection.
+    Initiator,
+    /// The peer opened the connection.
+    Responder,
+}
+
 /// A new peer request, sent to the `Player`.
-pub struct NewPeer(pub TcpStream);
+pub struct NewPeer(pub TcpStream, pub Role);
 
 /// A remove peer request, sent to the `Player`.
 pub struct RemovePeer(pub SocketAddr);
 
 /// An error associated with inserting a transaction into the mempool.
 pub enum MempoolError {
+    /// The contract failed to initialize.
     VM(VMSpawnError),
+    /// The transaction is already in the mempool.
+    Duplicate,
+    /// A different transaction in the mempool has the same short ID.
+    ShortIdCollision,
+    /// The mempool is full and the transaction is older than its contents.
+    Full,
+    /// A field exceeded its size limit.
+    Oversized {
+        field: &'static str,
+        len: usize,
+        limit: usize,
+    },
+    /// The timestamp is too far ahead of local time.
+    FutureTimestamp {
+        timestamp: u64,
+        now: u64,
+    },
+    /// The timestamp is too far behind local time.
+    StaleTimestamp {
+        timestamp: u64,
+        now: u64,
+    },
+    /// Initializing the contract exceeded the cost ceiling.
+    CostExceeded {
+        cost: u128,
+        limit: u128,
+    },
+    Storage(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::VM(err) => writeln!(f, "contract initialization failed; {}", err),
+            Self::Duplicate => writeln!(f, "duplicate transaction"),
+            Self::ShortIdCollision => writeln!(f, "short ID collides with another transaction"),
+            Self::Full => writeln!(f, "mempool full"),
+            Self::Oversized { field, len, limit } => {
+                writeln!(f, "{} length {} exceeds limit {}", field, len, limit)
+            }
+            Self::FutureTimestamp { timestamp, now } => {
+                writeln!(f, "timestamp {} is too far ahead of {}", timestamp, now)
+            }
+            Self::StaleTimestamp { timestamp, now } => {
+                writeln!(f, "timestamp {} is too far behind {}", timestamp, now)
+            }
+            Self::CostExceeded { cost, limit } => {
+                writeln!(f, "initialization cost {} exceeds limit {}", cost, limit)
+            }
+            Self::Storage(err) => writeln!(f, "storage error; {}", err),
+        }
+    }
+}
+
+/// An error encountered during the handshake.
+#[derive(Debug)]
+pub enum HandshakeError {
+    /// The connection was closed or failed.
+    Closed,
+    /// The peer sent an unexpected message.
+    UnexpectedMessage,
+    /// The peer is on a different network.
+    MagicBytes,
+    /// The peer uses an incompatible protocol version.
+    Version(u32),
+    /// The peer failed to complete the handshake in time.
+    Timeout,
+    /// The Noise handshake failed.
+    Noise,
+    /// The peer failed to prove ownership of the public key in its `Hello`.
+    Signature,
+    /// The peer radius exceeds our message limits.
+    Radius(u32),
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Closed => writeln!(f, "connection closed"),
+            Self::UnexpectedMessage => writeln!(f, "unexpected message"),
+            Self::MagicBytes => writeln!(f, "unexpected magic bytes"),
+            Self::Version(version) => writeln!(f, "incompatible version {}", version),
+            Self::Timeout => writeln!(f, "handshake timed out"),
+            Self::Noise => writeln!(f, "noise handshake failed"),
+            Self::Signature => writeln!(f, "invalid handshake signature"),
+            Self::Radius(radius) => writeln!(f, "unsupported radius {}", radius),
+        }
+    }
 }
 
 /// An error associated with adding a new peer.
 pub enum NewPeerError {
     Network(std::io::Error),
+    Handshake(HandshakeError),
     Arena(InsertPeerError),
 }
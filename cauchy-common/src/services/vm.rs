@@ -1,9 +1,73 @@
+use std::fmt;
+
+use crate::network::{Transaction, TxId};
+
+#[derive(Debug)]
 pub enum VMSpawnError {
     Spawn(VMError),
+    /// The message was addressed to a contract which has not been deployed.
+    MissingContract,
+    Storage(Box<dyn std::error::Error + Send + Sync>),
+    /// The VM task panicked or was cancelled.
+    Aborted,
+}
+
+impl fmt::Display for VMSpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Spawn(err) => writeln!(f, "vm error; {}", err),
+            Self::MissingContract => writeln!(f, "missing contract"),
+            Self::Storage(err) => writeln!(f, "storage error; {}", err),
+            Self::Aborted => writeln!(f, "vm aborted"),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum VMError {
     BadStatus(u32),
+    /// The stored contract state could not be decoded.
+    BadState,
+    /// The binary is not a valid module.
+    Decode,
+    /// The module could not be instantiated.
+    Instantiate,
+    /// The module does not export the function.
+    MissingExport(String),
+    /// Execution was aborted after exceeding its cost ceiling.
+    OutOfFuel(u128),
     Unknown,
 }
+
+impl fmt::Display for VMError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadStatus(status) => writeln!(f, "bad status {}", status),
+            Self::BadState => writeln!(f, "bad contract state"),
+            Self::Decode => writeln!(f, "invalid module"),
+            Self::Instantiate => writeln!(f, "failed to instantiate module"),
+            Self::MissingExport(func) => writeln!(f, "missing export {}", func),
+            Self::OutOfFuel(cost) => writeln!(f, "out of fuel after cost {}", cost),
+            Self::Unknown => writeln!(f, "unknown error"),
+        }
+    }
+}
+
+/// A request to execute a transaction, aborting once its cost exceeds `max_cost`.
+pub struct Execute {
+    pub tx: Transaction,
+    pub max_cost: u128,
+}
+
+/// The result of an execution whose cost is metered.
+pub trait Metered {
+    fn cost(&self) -> u128;
+}
+
+/// The result of an execution, holding the contract state to persist once the transaction is admitted.
+pub trait Stateful {
+    /// ID of the transaction which deployed the contract.
+    fn contract_id(&self) -> TxId;
+
+    fn state(&self) -> &[u8];
+}
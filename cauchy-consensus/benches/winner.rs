@@ -3,22 +3,25 @@ use criterion::*;
 use num_bigint::BigUint;
 use rand::prelude::*;
 
+const SIZES: &[usize] = &[64, 256, 1024, 4096, 16384];
+
 fn random() -> Entry {
     let mut rng = rand::thread_rng();
     let mut oddsketch = vec![0; ODDSKETCH_LEN];
     for i in 0..oddsketch.len() {
         oddsketch[i] = rng.gen();
     }
-    let mass: u8 = rng.gen();
+    let mass: [u8; MASS_BITS / 8] = rng.gen();
     Entry {
+        pubkey: rng.gen::<[u8; 32]>().to_vec(),
         oddsketch,
-        mass: BigUint::from(mass),
+        mass: BigUint::from_bytes_be(&mass),
     }
 }
 
 fn criterion_benchmark(c: &mut Criterion) {
     let mut group = c.benchmark_group("calc winner");
-    for n in (64..(128 + 1)).step_by(32) {
+    for &n in SIZES {
         group.throughput(Throughput::Elements(n as u64));
         let mut entries = Vec::with_capacity(n);
         for _ in 0..n {
@@ -34,7 +37,7 @@ fn criterion_benchmark(c: &mut Criterion) {
 
 fn criterion_benchmark_par(c: &mut Criterion) {
     let mut group = c.benchmark_group("calc winner par");
-    for n in (64..(128 + 1)).step_by(32) {
+    for &n in SIZES {
         group.throughput(Throughput::Elements(n as u64));
         let mut entries = Vec::with_capacity(n);
         for _ in 0..n {
@@ -0,0 +1,324 @@
+use std::{collections::HashSet, fmt};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use common::network::Status;
+
+use crate::{calculate_winner, Entry, RoundId};
+
+/// A status, along with the public key of the node which signed it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignedStatus {
+    pub pubkey: Bytes,
+    pub status: Status,
+}
+
+impl SignedStatus {
+    fn to_entry(&self) -> Entry {
+        Entry::from_status(&self.pubkey, self.status.clone())
+    }
+}
+
+/// A record of a round, sufficient for anyone to check which entries were considered and recompute the winner.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoundCertificate {
+    pub round: RoundId,
+    /// The statuses considered, in canonical order of their entries.
+    pub statuses: Vec<SignedStatus>,
+    /// The public key of the winner.
+    pub winner: Bytes,
+}
+
+/// An error encountered while verifying a `RoundCertificate`.
+#[derive(Debug, PartialEq)]
+pub enum CertificateError {
+    /// The certificate contains no statuses.
+    Empty,
+    /// More than one status is from the same public key.
+    Duplicate(Bytes),
+    /// A status belongs to a different round.
+    WrongRound(Bytes),
+    /// A status has an invalid signature.
+    InvalidSignature(Bytes),
+    /// The recomputed winner differs from the one claimed.
+    WrongWinner { expected: Bytes, claimed: Bytes },
+}
+
+impl fmt::Display for CertificateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => writeln!(f, "empty certificate"),
+            Self::Duplicate(pubkey) => writeln!(f, "duplicate status from {:?}", pubkey),
+            Self::WrongRound(pubkey) => writeln!(f, "status from {:?} has wrong round", pubkey),
+            Self::InvalidSignature(pubkey) => {
+                writeln!(f, "status from {:?} has invalid signature", pubkey)
+            }
+            Self::WrongWinner { expected, claimed } => {
+                writeln!(f, "winner is {:?} not {:?}", expected, claimed)
+            }
+        }
+    }
+}
+
+/// Find a public key with more than one status.
+fn find_duplicate(statuses: &[SignedStatus]) -> Option<Bytes> {
+    let mut pubkeys = HashSet::with_capacity(statuses.len());
+    statuses
+        .iter()
+        .find(|signed| !pubkeys.insert(&signed.pubkey))
+        .map(|signed| signed.pubkey.clone())
+}
+
+fn put_bytes(dst: &mut BytesMut, raw: &[u8]) {
+    dst.put_u32(raw.len() as u32);
+    dst.put_slice(raw);
+}
+
+fn get_bytes(src: &mut Bytes) -> Option<Bytes> {
+    if src.remaining() < 4 {
+        return None;
+    }
+    let len = src.get_u32() as usize;
+    if src.remaining() < len {
+        return None;
+    }
+    Some(src.split_to(len))
+}
+
+impl RoundCertificate {
+    /// Certify a round from the statuses considered, each of which must be from a distinct public key.
+    pub fn new(round: RoundId, statuses: Vec<SignedStatus>) -> Result<Self, CertificateError> {
+        if let Some(pubkey) = find_duplicate(&statuses) {
+            return Err(CertificateError::Duplicate(pubkey));
+        }
+        let mut pairs: Vec<(Entry, SignedStatus)> = statuses
+            .into_iter()
+            .map(|signed| (signed.to_entry(), signed))
+            .collect();
+        pairs.sort_by(|(entry_a, _), (entry_b, _)| entry_a.canonical_cmp(entry_b));
+        let (entries, statuses): (Vec<Entry>, Vec<SignedStatus>) = pairs.into_iter().unzip();
+        let winner = calculate_winner(&entries).ok_or(CertificateError::Empty)?;
+        let winner = Bytes::from(winner.pubkey.clone());
+        Ok(Self {
+            round,
+            statuses,
+            winner,
+        })
+    }
+
+    /// Serialize the certificate.
+    ///
+    /// Layout: `round (u64) || winner || n (u32) || (pubkey || oddsketch || root || nonce (u64) || round (u64) ||
+    /// signature)*`, where variable length fields are prefixed by their length as a `u32`.
+    pub fn to_bytes(&self) -> Bytes {
+        let mut raw = BytesMut::new();
+        raw.put_u64(self.round);
+        put_bytes(&mut raw, &self.winner);
+        raw.put_u32(self.statuses.len() as u32);
+        for SignedStatus { pubkey, status } in &self.statuses {
+            put_bytes(&mut raw, pubkey);
+            put_bytes(&mut raw, &status.oddsketch);
+            put_bytes(&mut raw, &status.root);
+            raw.put_u64(status.nonce);
+            raw.put_u64(status.round);
+            put_bytes(&mut raw, &status.signature);
+        }
+        raw.freeze()
+    }
+
+    /// Deserialize a certificate, `None` if it is malformed.
+    pub fn from_bytes(mut raw: Bytes) -> Option<Self> {
+        if raw.remaining() < 8 {
+            return None;
+        }
+        let round = raw.get_u64();
+        let winner = get_bytes(&mut raw)?;
+        if raw.remaining() < 4 {
+            return None;
+        }
+        let n_statuses = raw.get_u32();
+        let mut statuses = Vec::new();
+        for _ in 0..n_statuses {
+            let pubkey = get_bytes(&mut raw)?;
+            let oddsketch = get_bytes(&mut raw)?;
+            let root = get_bytes(&mut raw)?;
+            if raw.remaining() < 16 {
+                return None;
+            }
+            let nonce = raw.get_u64();
+            let status_round = raw.get_u64();
+            let signature = get_bytes(&mut raw)?;
+            statuses.push(SignedStatus {
+                pubkey,
+                status: Status {
+                    oddsketch,
+                    root,
+                    nonce,
+                    round: status_round,
+                    signature,
+                },
+            });
+        }
+        if raw.has_remaining() {
+            return None;
+        }
+        Some(Self {
+            round,
+            statuses,
+            winner,
+        })
+    }
+}
+
+/// Verify a certificate.
+///
+/// Each status must be from a distinct public key, belong to the certified round and be signed by its node key. The
+/// masses and weighted distances are recomputed from the statuses, and the resulting winner must match the one
+/// claimed.
+pub fn verify_certificate(certificate: &RoundCertificate) -> Result<(), CertificateError> {
+    if let Some(pubkey) = find_duplicate(&certificate.statuses) {
+        return Err(CertificateError::Duplicate(pubkey));
+    }
+    for SignedStatus { pubkey, status } in &certificate.statuses {
+        if status.round != certificate.round {
+            return Err(CertificateError::WrongRound(pubkey.clone()));
+        }
+        if !status.verify(pubkey) {
+            return Err(CertificateError::InvalidSignature(pubkey.clone()));
+        }
+    }
+
+    let entries: Vec<Entry> = certificate
+        .statuses
+        .iter()
+        .map(SignedStatus::to_entry)
+        .collect();
+    let winner = calculate_winner(&entries).ok_or(CertificateError::Empty)?;
+    if winner.pubkey[..] != certificate.winner[..] {
+        return Err(CertificateError::WrongWinner {
+            expected: Bytes::from(winner.pubkey.clone()),
+            claimed: certificate.winner.clone(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crypto::Keypair;
+    use rand::rngs::OsRng;
+
+    use super::*;
+    use crate::ODDSKETCH_LEN;
+
+    fn signed_status(keypair: &Keypair, round: RoundId, nonce: u64) -> SignedStatus {
+        let mut status = Status {
+            oddsketch: Bytes::from(vec![nonce as u8; ODDSKETCH_LEN]),
+            root: Bytes::from(vec![0; 32]),
+            nonce,
+            round,
+            signature: Bytes::new(),
+        };
+        status.sign(keypair);
+        SignedStatus {
+            pubkey: Bytes::copy_from_slice(&keypair.public.to_bytes()),
+            status,
+        }
+    }
+
+    fn keypairs(n: usize) -> Vec<Keypair> {
+        (0..n).map(|_| Keypair::generate(&mut OsRng)).collect()
+    }
+
+    #[test]
+    fn verify() {
+        let keypairs = keypairs(4);
+        let statuses: Vec<SignedStatus> = keypairs
+            .iter()
+            .enumerate()
+            .map(|(i, keypair)| signed_status(keypair, 5, i as u64))
+            .collect();
+        assert_eq!(
+            RoundCertificate::new(5, Vec::new()),
+            Err(CertificateError::Empty)
+        );
+        let certificate = RoundCertificate::new(5, statuses.clone()).unwrap();
+        assert_eq!(verify_certificate(&certificate), Ok(()));
+
+        // The winner is independent of the order statuses were received in
+        let mut reversed = statuses.clone();
+        reversed.reverse();
+        assert_eq!(RoundCertificate::new(5, reversed), Ok(certificate.clone()));
+
+        let decoded = RoundCertificate::from_bytes(certificate.to_bytes()).unwrap();
+        assert_eq!(decoded, certificate);
+        let raw = certificate.to_bytes();
+        assert_eq!(
+            RoundCertificate::from_bytes(raw.slice(..raw.len() - 1)),
+            None
+        );
+
+        let mut forged = certificate.clone();
+        forged.winner = certificate
+            .statuses
+            .iter()
+            .map(|signed| signed.pubkey.clone())
+            .find(|pubkey| *pubkey != certificate.winner)
+            .unwrap();
+        match verify_certificate(&forged) {
+            Err(CertificateError::WrongWinner { expected, .. }) => {
+                assert_eq!(expected, certificate.winner)
+            }
+            _ => panic!("expected wrong winner"),
+        }
+
+        let mut forged = certificate.clone();
+        forged.statuses[0].status.nonce += 1;
+        let pubkey = forged.statuses[0].pubkey.clone();
+        assert_eq!(
+            verify_certificate(&forged),
+            Err(CertificateError::InvalidSignature(pubkey))
+        );
+
+        let mut forged = certificate.clone();
+        forged.round += 1;
+        assert!(matches!(
+            verify_certificate(&forged),
+            Err(CertificateError::WrongRound(_))
+        ));
+
+        // A node cannot enter twice, whether certifying or verifying
+        let mut duplicated = statuses.clone();
+        duplicated.push(signed_status(&keypairs[0], 5, 7));
+        let pubkey = duplicated[0].pubkey.clone();
+        assert_eq!(
+            RoundCertificate::new(5, duplicated.clone()),
+            Err(CertificateError::Duplicate(pubkey.clone()))
+        );
+        let mut forged = certificate.clone();
+        forged.statuses = duplicated;
+        assert_eq!(
+            verify_certificate(&forged),
+            Err(CertificateError::Duplicate(pubkey))
+        );
+
+        let mut forged = certificate;
+        forged.statuses.clear();
+        assert_eq!(verify_certificate(&forged), Err(CertificateError::Empty));
+    }
+
+    #[test]
+    fn large_fields() {
+        // Fields longer than a `u16` length survive the round trip
+        let mut signed = signed_status(&keypairs(1)[0], 5, 0);
+        signed.status.oddsketch = Bytes::from(vec![1; 1 << 17]);
+        let certificate = RoundCertificate {
+            round: 5,
+            winner: signed.pubkey.clone(),
+            statuses: vec![signed],
+        };
+        let raw = certificate.to_bytes();
+        assert_eq!(RoundCertificate::from_bytes(raw.clone()), Some(certificate));
+        assert_eq!(RoundCertificate::from_bytes(raw.slice(..1 << 16)), None);
+    }
+}
@@ -1,8 +1,18 @@
+mod certificate;
+mod round;
+mod uint;
+
+pub use certificate::*;
+pub use round::*;
+pub use uint::MASS_BITS;
+
+use std::cmp::Ordering;
+
 use num_bigint::BigUint;
 use rayon::prelude::*;
 
 use common::network::Status;
-use crypto::blake3;
+use uint::U320;
 
 pub const ODDSKETCH_LEN: usize = 32;
 
@@ -12,8 +22,10 @@ pub fn get_version() -> String {
 }
 
 /// An entry into the consensus competition.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Entry {
+    /// The node public key, identifying the player.
+    pub pubkey: Vec<u8>,
     pub oddsketch: Vec<u8>,
     pub mass: BigUint,
 }
@@ -21,66 +33,198 @@ pub struct Entry {
 impl Entry {
     /// Construct an `Entry` from a public key and status.
     ///
-    /// The mass term is calculated by `H(pubkey || root || nonce )`.
+    /// The mass term is the digest maximized by the miner, `H(site || nonce)` where `site = H(pubkey || root)`.
     pub fn from_status(pubkey: &[u8], status: Status) -> Self {
         let oddsketch = status.oddsketch.to_vec();
-        let raw_nonce = status.nonce.to_be_bytes();
-        let preimage = [pubkey, &status.root, &raw_nonce].concat();
-        let hash = blake3::hash(&preimage);
-        let raw_mass = &hash.as_bytes()[..];
-        let mass = BigUint::from_bytes_be(raw_mass);
-        Self { oddsketch, mass }
+        let site = miner::site(pubkey, &status.root);
+        let raw_mass = miner::digest(&site, status.nonce);
+        let mass = BigUint::from_bytes_be(&raw_mass);
+        Self {
+            pubkey: pubkey.to_vec(),
+            oddsketch,
+            mass,
+        }
+    }
+
+    /// Compare entries in canonical order, by public key, oddsketch and then mass.
+    pub fn canonical_cmp(&self, other: &Entry) -> Ordering {
+        (&self.pubkey, &self.oddsketch, &self.mass).cmp(&(
+            &other.pubkey,
+            &other.oddsketch,
+            &other.mass,
+        ))
     }
 }
 
-/// Calculate the winner among all entries.
-pub fn calculate_winner(entries: &[Entry]) -> Option<usize> {
-    entries
-        .iter()
-        .enumerate()
-        .min_by_key(move |(_, entry_a)| {
-            entries.iter().fold(
-                BigUint::from(0 as u32),
-                |weight: BigUint, entry_b: &Entry| {
-                    // Calculate Hamming distance
-                    let dist: u32 = entry_a
-                        .oddsketch
-                        .iter()
-                        .zip(entry_b.oddsketch.iter())
-                        .fold(0, |total, (byte_a, byte_b)| {
-                            total + (byte_a ^ byte_b).count_ones()
-                        });
-                    // Add weighted distance
-                    weight + dist * entry_b.mass.clone()
-                },
-            )
+/// Sort entries into canonical order.
+pub fn sort_canonical(entries: &mut [Entry]) {
+    entries.sort_by(Entry::canonical_cmp);
+}
+
+/// Iterate over the indices of the set bits of an oddsketch, a word at a time.
+fn set_bits(oddsketch: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    oddsketch.chunks(8).enumerate().flat_map(|(index, chunk)| {
+        let mut raw = [0; 8];
+        raw[..chunk.len()].copy_from_slice(chunk);
+        let mut word = u64::from_le_bytes(raw);
+        std::iter::from_fn(move || {
+            if word == 0 {
+                return None;
+            }
+            let bit = word.trailing_zeros() as usize;
+            word &= word - 1;
+            Some(index * 64 + bit)
         })
-        .map(|(i, _)| i)
+    })
+}
+
+/// Per-bit mass sums over all entries.
+///
+/// The weight of an entry is the sum, over all entries, of the Hamming distance between their oddsketches
+/// multiplied by the others mass, where the distance only covers the bytes both oddsketches share. Summing by bit
+/// rather than by pair, an entry contributes its mass at every bit where it differs from the given entry. The
+/// weight is therefore calculable in O(bits) from these sums, rather than O(n) from the entries.
+struct BitSums {
+    /// Total mass of the entries with each bit set.
+    ones: Vec<U320>,
+    /// Total mass of the entries whose oddsketch covers each byte.
+    present: Vec<U320>,
+    /// Prefix sums of `ones`.
+    prefix: Vec<U320>,
+}
+
+impl BitSums {
+    /// Construct from the per-bit sums and the total mass of the entries with each oddsketch length.
+    fn from_parts(ones: Vec<U320>, by_len: Vec<U320>) -> Self {
+        let mut present = vec![U320::default(); by_len.len() - 1];
+        let mut total = U320::default();
+        for (index, mass) in by_len.iter().enumerate().skip(1).rev() {
+            total += mass;
+            present[index - 1] = total;
+        }
+
+        let mut prefix = Vec::with_capacity(ones.len() + 1);
+        let mut total = U320::default();
+        prefix.push(total);
+        for mass in &ones {
+            total += mass;
+            prefix.push(total);
+        }
+
+        Self {
+            ones,
+            present,
+            prefix,
+        }
+    }
+
+    fn new(entries: &[Entry]) -> Self {
+        let len = entries
+            .iter()
+            .map(|entry| entry.oddsketch.len())
+            .max()
+            .unwrap_or(0);
+        let mut ones = vec![U320::default(); 8 * len];
+        let mut by_len = vec![U320::default(); len + 1];
+        for entry in entries {
+            let mass = U320::from_mass(&entry.mass);
+            by_len[entry.oddsketch.len()] += &mass;
+            for bit in set_bits(&entry.oddsketch) {
+                ones[bit] += &mass;
+            }
+        }
+        Self::from_parts(ones, by_len)
+    }
+
+    fn new_par(entries: &[Entry]) -> Self {
+        let len = entries
+            .iter()
+            .map(|entry| entry.oddsketch.len())
+            .max()
+            .unwrap_or(0);
+        let identity = || {
+            (
+                vec![U320::default(); 8 * len],
+                vec![U320::default(); len + 1],
+            )
+        };
+        let (ones, by_len) = entries
+            .par_iter()
+            .fold(identity, |(mut ones, mut by_len), entry| {
+                let mass = U320::from_mass(&entry.mass);
+                by_len[entry.oddsketch.len()] += &mass;
+                for bit in set_bits(&entry.oddsketch) {
+                    ones[bit] += &mass;
+                }
+                (ones, by_len)
+            })
+            .reduce(
+                identity,
+                |(mut ones_a, mut by_len_a), (ones_b, by_len_b)| {
+                    for (a, b) in ones_a.iter_mut().zip(ones_b.iter()) {
+                        *a += b;
+                    }
+                    for (a, b) in by_len_a.iter_mut().zip(by_len_b.iter()) {
+                        *a += b;
+                    }
+                    (ones_a, by_len_a)
+                },
+            );
+        Self::from_parts(ones, by_len)
+    }
+
+    /// The total weighted distance from an oddsketch to every entry.
+    fn weight(&self, oddsketch: &[u8]) -> U320 {
+        // Entries with the bit set contribute at unset bits, the remainder of those present at set bits
+        let mut weight = self.prefix[8 * oddsketch.len()];
+        let mut surplus = U320::default();
+        for bit in set_bits(oddsketch) {
+            weight += &self.present[bit / 8];
+            surplus += &self.ones[bit];
+            surplus += &self.ones[bit];
+        }
+        weight -= &surplus;
+        weight
+    }
+}
+
+/// Choose the winner given the weight of each entry.
+///
+/// The entry with the least weight wins. Ties are broken by the greatest mass and then canonical order, so the
+/// winner is independent of the order of the entries.
+fn winning_index(entries: &[Entry], weights: &[U320]) -> Option<usize> {
+    (0..entries.len()).min_by(|&index_a, &index_b| {
+        let (entry_a, entry_b) = (&entries[index_a], &entries[index_b]);
+        weights[index_a]
+            .cmp(&weights[index_b])
+            .then_with(|| entry_b.mass.cmp(&entry_a.mass))
+            .then_with(|| entry_a.canonical_cmp(entry_b))
+    })
+}
+
+/// Calculate the index of the winner among all entries.
+pub(crate) fn calculate_winner_index(entries: &[Entry]) -> Option<usize> {
+    let sums = BitSums::new(entries);
+    let weights: Vec<U320> = entries
+        .iter()
+        .map(|entry| sums.weight(&entry.oddsketch))
+        .collect();
+    winning_index(entries, &weights)
+}
+
+/// Calculate the winner among all entries.
+pub fn calculate_winner(entries: &[Entry]) -> Option<&Entry> {
+    calculate_winner_index(entries).map(|index| &entries[index])
 }
 
 /// Calculate the winner among all entries. Performed in parallel.
-pub fn calculate_winner_par(entries: &[Entry]) -> Option<usize> {
-    entries
+pub fn calculate_winner_par(entries: &[Entry]) -> Option<&Entry> {
+    let sums = BitSums::new_par(entries);
+    let weights: Vec<U320> = entries
         .par_iter()
-        .enumerate()
-        .min_by_key(move |(_, entry_a)| {
-            entries
-                .par_iter()
-                .map(|entry_b| {
-                    // Calculate Hamming distance
-                    let dist = entry_a
-                        .oddsketch
-                        .iter()
-                        .zip(entry_b.oddsketch.iter())
-                        .fold(0, |total, (byte_a, byte_b)| {
-                            total + (byte_a ^ byte_b).count_ones()
-                        });
-                    // Weighted distance
-                    entry_b.mass.clone() * dist
-                })
-                .sum::<BigUint>()
-        })
-        .map(|(i, _)| i)
+        .map(|entry| sums.weight(&entry.oddsketch))
+        .collect();
+    winning_index(entries, &weights).map(|index| &entries[index])
 }
 
 #[cfg(test)]
@@ -97,12 +241,29 @@ mod tests {
             }
             let mass: u8 = rng.gen();
             Entry {
+                pubkey: rng.gen::<[u8; 32]>().to_vec(),
                 oddsketch,
                 mass: BigUint::from(mass),
             }
         }
     }
 
+    #[test]
+    fn mass_matches_miner() {
+        let pubkey = [1; 32];
+        let root = [2; 32];
+        let status = Status {
+            oddsketch: vec![0; ODDSKETCH_LEN].into(),
+            root: root.to_vec().into(),
+            nonce: 42,
+            round: 0,
+            signature: Default::default(),
+        };
+        let entry = Entry::from_status(&pubkey, status);
+        let digest = miner::digest(&miner::site(&pubkey, &root), 42);
+        assert_eq!(entry.mass, BigUint::from_bytes_be(&digest));
+    }
+
     #[test]
     fn empty() {
         let entries = Vec::new();
@@ -115,8 +276,8 @@ mod tests {
         let mut entries = Vec::new();
         let entry = Entry::random();
         entries.push(entry.clone());
-        assert_eq!(calculate_winner(&entries), Some(0));
-        assert_eq!(calculate_winner_par(&entries), Some(0));
+        assert_eq!(calculate_winner(&entries), Some(&entry));
+        assert_eq!(calculate_winner_par(&entries), Some(&entry));
     }
 
     #[test]
@@ -130,8 +291,8 @@ mod tests {
         entries.push(entry_a);
         entries.push(entry_b);
 
-        assert_eq!(calculate_winner(&entries), Some(1));
-        assert_eq!(calculate_winner_par(&entries), Some(1))
+        assert_eq!(calculate_winner(&entries), Some(&entries[1]));
+        assert_eq!(calculate_winner_par(&entries), Some(&entries[1]))
     }
 
     #[test]
@@ -149,7 +310,95 @@ mod tests {
         winner.mass = total_mass + 1 as u32;
         entries.insert(n, winner);
 
-        assert_eq!(calculate_winner(&entries), Some(n));
-        assert_eq!(calculate_winner_par(&entries), Some(n))
+        assert_eq!(calculate_winner(&entries), Some(&entries[n]));
+        assert_eq!(calculate_winner_par(&entries), Some(&entries[n]))
+    }
+
+    #[test]
+    fn tie_break() {
+        let mut entries: Vec<Entry> = (0..16).map(|_| Entry::random()).collect();
+        let winner = calculate_winner(&entries).cloned();
+
+        // The winner is independent of order
+        entries.shuffle(&mut thread_rng());
+        assert_eq!(calculate_winner(&entries).cloned(), winner);
+        sort_canonical(&mut entries);
+        assert_eq!(calculate_winner_par(&entries).cloned(), winner);
+
+        // Equal weights are broken by the greatest mass, then the least public key
+        let mut entry = Entry::random();
+        entry.oddsketch = vec![0; ODDSKETCH_LEN];
+        entry.mass = BigUint::from(1u32);
+        let mut heavy = entry.clone();
+        heavy.pubkey = vec![2];
+        heavy.mass = BigUint::from(2u32);
+        let mut light_a = entry.clone();
+        light_a.pubkey = vec![0];
+        let mut light_b = entry;
+        light_b.pubkey = vec![1];
+        let entries = vec![light_b.clone(), heavy.clone(), light_a.clone()];
+        assert_eq!(calculate_winner(&entries), Some(&heavy));
+        let entries = vec![light_b, light_a.clone()];
+        assert_eq!(calculate_winner(&entries), Some(&light_a));
+        assert_eq!(calculate_winner_par(&entries), Some(&light_a));
+    }
+
+    /// The pairwise calculation, over arbitrary precision integers.
+    fn naive_winner(entries: &[Entry]) -> Option<&Entry> {
+        let weight = |entry_a: &Entry| {
+            entries
+                .iter()
+                .map(|entry_b| {
+                    let dist: u32 = entry_a
+                        .oddsketch
+                        .iter()
+                        .zip(entry_b.oddsketch.iter())
+                        .map(|(byte_a, byte_b)| (byte_a ^ byte_b).count_ones())
+                        .sum();
+                    dist * entry_b.mass.clone()
+                })
+                .sum::<BigUint>()
+        };
+        entries.iter().min_by(|entry_a, entry_b| {
+            weight(entry_a)
+                .cmp(&weight(entry_b))
+                .then_with(|| entry_b.mass.cmp(&entry_a.mass))
+                .then_with(|| entry_a.canonical_cmp(entry_b))
+        })
+    }
+
+    #[test]
+    fn matches_naive() {
+        let mut rng = rand::thread_rng();
+        for n in 1..64 {
+            // Full width masses, with oddsketches of varying length
+            let entries: Vec<Entry> = (0..n)
+                .map(|_| {
+                    let len = rng.gen_range(ODDSKETCH_LEN / 2, ODDSKETCH_LEN + 1);
+                    let oddsketch = (0..len).map(|_| rng.gen()).collect();
+                    let mass: [u8; MASS_BITS / 8] = rng.gen();
+                    Entry {
+                        pubkey: rng.gen::<[u8; 32]>().to_vec(),
+                        oddsketch,
+                        mass: BigUint::from_bytes_be(&mass),
+                    }
+                })
+                .collect();
+            let winner = naive_winner(&entries);
+            assert_eq!(calculate_winner(&entries), winner);
+            assert_eq!(calculate_winner_par(&entries), winner);
+
+            // Ties are frequent with sparse oddsketches
+            let entries: Vec<Entry> = (0..n)
+                .map(|_| Entry {
+                    pubkey: vec![rng.gen_range(0, 4)],
+                    oddsketch: vec![1 << rng.gen_range(0, 2); 2],
+                    mass: BigUint::from(rng.gen_range(0u8, 2)),
+                })
+                .collect();
+            let winner = naive_winner(&entries);
+            assert_eq!(calculate_winner(&entries), winner);
+            assert_eq!(calculate_winner_par(&entries), winner);
+        }
     }
 }
@@ -0,0 +1,203 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use common::network::RoundId;
+
+use crate::{calculate_winner_index, Entry};
+
+/// The duration of a round in milliseconds.
+pub const ROUND_MS: u64 = 10_000;
+
+/// The round containing `time`, in milliseconds since the Unix epoch.
+pub fn round_at(time: u64) -> RoundId {
+    time / ROUND_MS
+}
+
+/// The current round, according to the local clock.
+pub fn current_round() -> RoundId {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap() // This is safe
+        .as_millis() as u64;
+    round_at(now)
+}
+
+/// The phase of a consensus round.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Phase {
+    /// Entries are being collected.
+    Collecting,
+    /// The winner has been chosen and its transaction set is being adopted.
+    Adopting,
+    /// The round is complete.
+    Finalized,
+}
+
+/// An error associated with a consensus round.
+#[derive(Debug, PartialEq)]
+pub enum RoundError {
+    /// The entry belongs to a different round.
+    Stale { round: RoundId, current: RoundId },
+    /// The round is in the wrong phase for the transition.
+    Phase(Phase),
+    /// An entry from the same public key has already been submitted.
+    Duplicate(Vec<u8>),
+}
+
+/// A consensus round.
+///
+/// Entries for the round are collected, the winner is chosen, its state is adopted and the round is finalized, in
+/// that order. Each entry is tagged with the player it was received from, and once the round is closed the
+/// entries are held in canonical order.
+#[derive(Clone, Debug)]
+pub struct Round<T> {
+    id: RoundId,
+    phase: Phase,
+    entries: Vec<(T, Entry)>,
+    winner: Option<usize>,
+}
+
+impl<T> Round<T> {
+    /// Begin collecting entries for a round.
+    pub fn new(id: RoundId) -> Self {
+        Self {
+            id,
+            phase: Phase::Collecting,
+            entries: Vec::new(),
+            winner: None,
+        }
+    }
+
+    pub fn id(&self) -> RoundId {
+        self.id
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    pub fn entries(&self) -> &[(T, Entry)] {
+        &self.entries
+    }
+
+    /// The winning entry, once the round is closed.
+    pub fn winner(&self) -> Option<&(T, Entry)> {
+        self.winner.map(|index| &self.entries[index])
+    }
+
+    fn expect_phase(&self, phase: Phase) -> Result<(), RoundError> {
+        if self.phase == phase {
+            Ok(())
+        } else {
+            Err(RoundError::Phase(self.phase))
+        }
+    }
+
+    /// Submit an entry, rejecting it if it belongs to another round or its public key has already submitted one.
+    pub fn submit(&mut self, round: RoundId, player: T, entry: Entry) -> Result<(), RoundError> {
+        self.expect_phase(Phase::Collecting)?;
+        if round != self.id {
+            return Err(RoundError::Stale {
+                round,
+                current: self.id,
+            });
+        }
+        if self
+            .entries
+            .iter()
+            .any(|(_, existing)| existing.pubkey == entry.pubkey)
+        {
+            return Err(RoundError::Duplicate(entry.pubkey));
+        }
+        self.entries.push((player, entry));
+        Ok(())
+    }
+
+    /// Stop collecting entries and choose the winner.
+    pub fn close(&mut self) -> Result<Option<&(T, Entry)>, RoundError> {
+        self.expect_phase(Phase::Collecting)?;
+        self.entries
+            .sort_by(|(_, entry_a), (_, entry_b)| entry_a.canonical_cmp(entry_b));
+        let entries: Vec<Entry> = self
+            .entries
+            .iter()
+            .map(|(_, entry)| entry.clone())
+            .collect();
+        self.winner = calculate_winner_index(&entries);
+        self.phase = Phase::Adopting;
+        Ok(self.winner())
+    }
+
+    /// Mark the winners state as adopted.
+    pub fn finalize(&mut self) -> Result<(), RoundError> {
+        self.expect_phase(Phase::Adopting)?;
+        self.phase = Phase::Finalized;
+        Ok(())
+    }
+
+    /// Begin the following round.
+    pub fn advance(self) -> Result<Self, RoundError> {
+        self.expect_phase(Phase::Finalized)?;
+        Ok(Self::new(self.id + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigUint;
+
+    use super::*;
+
+    fn entry(mass: u8) -> Entry {
+        Entry {
+            pubkey: vec![mass],
+            oddsketch: vec![mass; crate::ODDSKETCH_LEN],
+            mass: BigUint::from(mass),
+        }
+    }
+
+    #[test]
+    fn lifecycle() {
+        let mut round = Round::new(7);
+        assert_eq!(round.phase(), Phase::Collecting);
+        round.submit(7, "a", entry(1)).unwrap();
+        round.submit(7, "b", entry(2)).unwrap();
+
+        // Stale entries are rejected
+        assert_eq!(
+            round.submit(6, "c", entry(3)),
+            Err(RoundError::Stale {
+                round: 6,
+                current: 7
+            })
+        );
+        assert_eq!(round.entries().len(), 2);
+
+        // Each public key may only submit once, so a player cannot enter twice
+        assert_eq!(
+            round.submit(7, "c", entry(2)),
+            Err(RoundError::Duplicate(vec![2]))
+        );
+        assert_eq!(round.entries().len(), 2);
+
+        // Transitions must occur in order
+        assert_eq!(round.finalize(), Err(RoundError::Phase(Phase::Collecting)));
+        assert_eq!(round.close().unwrap().map(|(player, _)| *player), Some("b"));
+        assert_eq!(round.phase(), Phase::Adopting);
+        assert_eq!(
+            round.submit(7, "c", entry(3)),
+            Err(RoundError::Phase(Phase::Adopting))
+        );
+        round.finalize().unwrap();
+
+        let round = round.advance().unwrap();
+        assert_eq!((round.id(), round.phase()), (8, Phase::Collecting));
+        assert!(round.winner().is_none());
+    }
+
+    #[test]
+    fn round_boundaries() {
+        assert_eq!(round_at(0), 0);
+        assert_eq!(round_at(ROUND_MS - 1), 0);
+        assert_eq!(round_at(ROUND_MS), 1);
+    }
+}
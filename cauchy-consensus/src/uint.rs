@@ -0,0 +1,77 @@
+use std::{
+    convert::TryInto,
+    ops::{AddAssign, SubAssign},
+};
+
+use num_bigint::BigUint;
+
+const LIMBS: usize = 5;
+
+/// The maximum width of a mass, in bits.
+pub const MASS_BITS: usize = 256;
+
+/// A fixed-width 320-bit unsigned integer, stored with the most significant limb first.
+///
+/// Masses are at most 256 bits, leaving 64 bits of headroom for summing them over entries and oddsketch bits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct U320([u64; LIMBS]);
+
+impl U320 {
+    /// Convert a mass.
+    ///
+    /// Panics if the mass exceeds `MASS_BITS`.
+    pub fn from_mass(mass: &BigUint) -> Self {
+        assert!(mass.bits() <= MASS_BITS, "mass exceeds {} bits", MASS_BITS);
+        let mut raw = mass.to_bytes_le();
+        raw.resize(LIMBS * 8, 0);
+        let mut limbs = [0; LIMBS];
+        for (limb, chunk) in limbs.iter_mut().rev().zip(raw.chunks(8)) {
+            *limb = u64::from_le_bytes(chunk.try_into().unwrap()); // This is safe
+        }
+        Self(limbs)
+    }
+}
+
+impl AddAssign<&U320> for U320 {
+    fn add_assign(&mut self, other: &U320) {
+        let mut carry = false;
+        for (limb, other_limb) in self.0.iter_mut().zip(other.0.iter()).rev() {
+            let (sum, overflow_a) = limb.overflowing_add(*other_limb);
+            let (sum, overflow_b) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = overflow_a || overflow_b;
+        }
+        debug_assert!(!carry, "overflow");
+    }
+}
+
+impl SubAssign<&U320> for U320 {
+    fn sub_assign(&mut self, other: &U320) {
+        let mut borrow = false;
+        for (limb, other_limb) in self.0.iter_mut().zip(other.0.iter()).rev() {
+            let (diff, underflow_a) = limb.overflowing_sub(*other_limb);
+            let (diff, underflow_b) = diff.overflowing_sub(borrow as u64);
+            *limb = diff;
+            borrow = underflow_a || underflow_b;
+        }
+        debug_assert!(!borrow, "underflow");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic() {
+        let max = BigUint::from_bytes_be(&[0xff; MASS_BITS / 8]);
+        let mut sum = U320::from_mass(&max);
+        sum += &U320::from_mass(&max);
+        assert_eq!(sum, U320([1, !0, !0, !0, !0 - 1]));
+        assert!(sum > U320::from_mass(&max));
+
+        sum -= &U320::from_mass(&max);
+        assert_eq!(sum, U320::from_mass(&max));
+        assert_eq!(U320::from_mass(&BigUint::from(3u32)), U320([0, 0, 0, 0, 3]));
+    }
+}
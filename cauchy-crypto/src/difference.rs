@@ -0,0 +1,45 @@
+/// Estimate the size of the symmetric difference between two sets from their oddsketches.
+///
+/// Returns `None` if the sketches are of different lengths or are saturated, in which case the
+/// difference is too large to estimate.
+pub fn estimate_difference(oddsketch_a: &[u8], oddsketch_b: &[u8]) -> Option<usize> {
+    if oddsketch_a.len() != oddsketch_b.len() || oddsketch_a.is_empty() {
+        return None;
+    }
+
+    // Hamming weight of the XOR
+    let weight: u32 = oddsketch_a
+        .iter()
+        .zip(oddsketch_b)
+        .map(|(a, b)| (a ^ b).count_ones())
+        .sum();
+
+    // A difference of d toggles each of the n bits with probability (1 - (1 - 2/n)^d) / 2
+    let n_bits = (oddsketch_a.len() * 8) as f64;
+    let ratio = 1. - 2. * weight as f64 / n_bits;
+    if ratio <= 0. {
+        return None;
+    }
+    let estimate = ratio.ln() / (1. - 2. / n_bits).ln();
+    Some(estimate.round() as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate() {
+        let zero = vec![0; 128];
+        assert_eq!(estimate_difference(&zero, &zero), Some(0));
+
+        let mut sparse = zero.clone();
+        sparse[0] = 0b1010_1010;
+        sparse[64] = 0b0000_0011;
+        assert_eq!(estimate_difference(&zero, &sparse), Some(6));
+
+        let saturated = vec![0xff; 128];
+        assert_eq!(estimate_difference(&zero, &saturated), None);
+        assert_eq!(estimate_difference(&zero, &zero[..64]), None);
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    convert::TryFrom,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+use ed25519_dalek::{PublicKey, Signature, Signer, Verifier};
+use rand::rngs::OsRng;
+
+pub use ed25519_dalek::Keypair;
+
+pub const PUBLIC_KEY_LEN: usize = ed25519_dalek::PUBLIC_KEY_LENGTH;
+pub const SIGNATURE_LEN: usize = ed25519_dalek::SIGNATURE_LENGTH;
+
+/// Write a secret to a new file at `path`, readable only by the owner.
+///
+/// Fails if the file already exists.
+pub fn write_secret<P: AsRef<Path>>(path: P, secret: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.as_ref().parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(secret)?;
+    file.sync_all()
+}
+
+/// Load the node keypair at `path`, generating and saving a new one if missing.
+pub fn load_keypair<P: AsRef<Path>>(path: P) -> io::Result<Keypair> {
+    match fs::read(&path) {
+        Ok(raw) => Keypair::from_bytes(&raw)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            let keypair = Keypair::generate(&mut OsRng);
+            write_secret(&path, &keypair.to_bytes())?;
+            Ok(keypair)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Sign a message.
+pub fn sign(keypair: &Keypair, message: &[u8]) -> [u8; SIGNATURE_LEN] {
+    keypair.sign(message).to_bytes()
+}
+
+/// Verify a signature, returning `false` if either the public key or signature is malformed.
+pub fn verify(pubkey: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let pubkey = match PublicKey::from_bytes(pubkey) {
+        Ok(ok) => ok,
+        Err(_) => return false,
+    };
+    let signature = match Signature::try_from(signature) {
+        Ok(ok) => ok,
+        Err(_) => return false,
+    };
+    pubkey.verify(message, &signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity");
+        let keypair = load_keypair(&path).unwrap();
+        let pubkey = keypair.public.to_bytes();
+
+        // Reloading yields the same keypair
+        assert_eq!(
+            load_keypair(&path).unwrap().to_bytes()[..],
+            keypair.to_bytes()[..]
+        );
+
+        let signature = sign(&keypair, b"message");
+        assert!(verify(&pubkey, b"message", &signature));
+        assert!(!verify(&pubkey, b"other message", &signature));
+        assert!(!verify(&[], b"message", &signature));
+        assert!(!verify(&pubkey, b"message", &signature[1..]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn secret_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keys").join("identity");
+        load_keypair(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // Existing files are never overwritten
+        let err = write_secret(&path, b"secret").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    }
+}
@@ -1,5 +1,10 @@
+mod difference;
+mod identity;
 pub mod merkle;
 
+pub use difference::*;
+pub use identity::*;
+
 pub use blake3;
 pub use minisketch_rs::{Minisketch, MinisketchError};
 pub use oddsketch::Oddsketch;
@@ -1,10 +1,55 @@
+mod treap;
+
+pub use treap::*;
+
 use digest::Digest;
-use std::marker::PhantomData;
+use std::{convert::TryInto, marker::PhantomData};
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+const ROOT_PREFIX: u8 = 2;
+const EMPTY_PREFIX: u8 = 3;
+
+/// The hashing scheme used by a `MerkleTree`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Leaves and nodes are hashed alike and the last node of odd rows is duplicated.
+    ///
+    /// This allows distinct leaf sets to share a root, it is kept for compatibility.
+    Legacy,
+    /// Leaves, nodes and the root are hashed with distinct prefixes.
+    ///
+    /// The last node of odd rows is paired with an empty marker and the root commits to the leaf count.
+    DomainSeparated,
+}
+
+impl Default for Mode {
+    fn default() -> Self {
+        Self::Legacy
+    }
+}
+
+fn prefixed_digest<HashT: Digest>(prefix: u8, parts: &[&[u8]]) -> Vec<u8> {
+    let preimage = [&[prefix][..], &parts.concat()].concat();
+    HashT::digest(&preimage).to_vec()
+}
+
+/// Number of rows above the leaves.
+fn depth(leaf_count: usize) -> usize {
+    let mut depth = 0;
+    let mut row_len = leaf_count;
+    while row_len > 1 {
+        row_len = (row_len + 1) / 2;
+        depth += 1;
+    }
+    depth
+}
 
 #[derive(Default)]
 pub struct MerkleTree<'a, HashT: Digest + Default> {
     pub tree: Option<Vec<Vec<Vec<u8>>>>,
     leaves: Vec<&'a [u8]>,
+    mode: Mode,
     _hasher: PhantomData<HashT>,
 }
 
@@ -13,11 +58,28 @@ impl<'a, HashT: Digest + Default> MerkleTree<'a, HashT> {
         MerkleTree::default()
     }
 
+    /// Construct a tree using the given hashing scheme.
+    pub fn with_mode(mode: Mode) -> Self {
+        MerkleTree {
+            mode,
+            ..Default::default()
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
     pub fn add_leaf(&mut self, hash: &'a dyn AsRef<[u8]>) {
         self.leaves.push(hash.as_ref())
     }
 
     pub fn build_tree(&mut self) {
+        if self.mode == Mode::DomainSeparated {
+            self.build_tree_separated();
+            return;
+        }
+
         let mut data: Vec<Vec<u8>> = self.leaves.iter().map(|b| Vec::from(*b)).collect();
         let mut tree = Vec::new();
         while {
@@ -38,6 +100,34 @@ impl<'a, HashT: Digest + Default> MerkleTree<'a, HashT> {
         self.tree = Some(tree);
     }
 
+    /// Build the tree in `Mode::DomainSeparated`.
+    ///
+    /// Rows run from the root down to the leaf hashes, the root row holds `H(2 || leaf count || top)`.
+    fn build_tree_separated(&mut self) {
+        let mut data: Vec<Vec<u8>> = self
+            .leaves
+            .iter()
+            .map(|leaf| prefixed_digest::<HashT>(LEAF_PREFIX, &[leaf]))
+            .collect();
+        let empty = prefixed_digest::<HashT>(EMPTY_PREFIX, &[]);
+        let mut tree = vec![data.clone()];
+        while data.len() > 1 {
+            data = data
+                .chunks(2)
+                .map(|c| {
+                    let right = c.get(1).unwrap_or(&empty);
+                    prefixed_digest::<HashT>(NODE_PREFIX, &[&c[0], right])
+                })
+                .collect();
+            tree.insert(0, data.clone());
+        }
+        let top = data.first().unwrap_or(&empty);
+        let leaf_count = (self.leaves.len() as u64).to_be_bytes();
+        let root = prefixed_digest::<HashT>(ROOT_PREFIX, &[&leaf_count, top]);
+        tree.insert(0, vec![root]);
+        self.tree = Some(tree);
+    }
+
     pub fn root(&self) -> Option<&[u8]> {
         if let Some(tree) = &self.tree {
             Some(&tree[0][0])
@@ -45,6 +135,160 @@ impl<'a, HashT: Digest + Default> MerkleTree<'a, HashT> {
             None
         }
     }
+
+    /// Construct an inclusion proof for the leaf at `index`.
+    ///
+    /// Returns `None` if the tree has not been built or `index` is out of bounds.
+    pub fn proof(&self, index: usize) -> Option<Proof> {
+        let tree = self.tree.as_ref()?;
+        if index >= self.leaves.len() {
+            return None;
+        }
+
+        if self.mode == Mode::DomainSeparated {
+            // Skip the root and top rows
+            let empty = prefixed_digest::<HashT>(EMPTY_PREFIX, &[]);
+            let mut idx = index;
+            let siblings = tree
+                .iter()
+                .skip(2)
+                .rev()
+                .map(|row| {
+                    let sibling = row.get(idx ^ 1).unwrap_or(&empty).clone();
+                    idx /= 2;
+                    sibling
+                })
+                .collect();
+            return Some(Proof {
+                index: index as u64,
+                siblings,
+            });
+        }
+
+        // Siblings are duplicated at the end of odd rows
+        let sibling = |row_len: usize, idx: usize| {
+            if idx ^ 1 < row_len {
+                idx ^ 1
+            } else {
+                idx
+            }
+        };
+
+        let mut siblings = Vec::with_capacity(tree.len());
+        siblings.push(self.leaves[sibling(self.leaves.len(), index)].to_vec());
+        let mut idx = index / 2;
+        for row in tree.iter().skip(1).rev() {
+            siblings.push(row[sibling(row.len(), idx)].clone());
+            idx /= 2;
+        }
+        Some(Proof {
+            index: index as u64,
+            siblings,
+        })
+    }
+}
+
+/// An authentication path from a leaf to the root.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Proof {
+    /// The index of the leaf.
+    pub index: u64,
+    /// Siblings from the bottom row upwards, the first is a leaf.
+    pub siblings: Vec<Vec<u8>>,
+}
+
+impl Proof {
+    /// Serialize the proof.
+    ///
+    /// Layout: `index (u64) || n (u8) || leaf len (u16) || leaf || hash len (u8) || hashes`, where the remaining
+    /// `n - 1` siblings are hashes of equal length.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut raw = self.index.to_be_bytes().to_vec();
+        raw.push(self.siblings.len() as u8);
+        if let Some((leaf, hashes)) = self.siblings.split_first() {
+            raw.extend_from_slice(&(leaf.len() as u16).to_be_bytes());
+            raw.extend_from_slice(leaf);
+            raw.push(hashes.first().map(Vec::len).unwrap_or(0) as u8);
+            for hash in hashes {
+                raw.extend_from_slice(hash);
+            }
+        }
+        raw
+    }
+
+    /// Deserialize a proof, returning `None` if malformed.
+    pub fn from_bytes(raw: &[u8]) -> Option<Self> {
+        let take = |raw: &mut &[u8], len: usize| -> Option<Vec<u8>> {
+            if raw.len() < len {
+                return None;
+            }
+            let (head, tail) = raw.split_at(len);
+            *raw = tail;
+            Some(head.to_vec())
+        };
+        let mut raw = raw;
+
+        let index = u64::from_be_bytes(take(&mut raw, 8)?.try_into().unwrap()); // This is safe
+        let n = take(&mut raw, 1)?[0] as usize;
+        let mut siblings = Vec::with_capacity(n);
+        if n > 0 {
+            let leaf_len = u16::from_be_bytes(take(&mut raw, 2)?.try_into().unwrap()); // This is safe
+            siblings.push(take(&mut raw, leaf_len as usize)?);
+            let hash_len = take(&mut raw, 1)?[0] as usize;
+            for _ in 1..n {
+                siblings.push(take(&mut raw, hash_len)?);
+            }
+        }
+        if !raw.is_empty() {
+            return None;
+        }
+        Some(Self { index, siblings })
+    }
+}
+
+/// Verify that `leaf` is included under `root`.
+pub fn verify_proof<HashT: Digest>(root: &[u8], leaf: &[u8], proof: &Proof) -> bool {
+    if proof.siblings.is_empty() {
+        return false;
+    }
+    let mut node = leaf.to_vec();
+    let mut idx = proof.index;
+    for sibling in &proof.siblings {
+        let preimage = if idx & 1 == 0 {
+            [&node[..], sibling].concat()
+        } else {
+            [&sibling[..], &node[..]].concat()
+        };
+        node = HashT::digest(&preimage).to_vec();
+        idx /= 2;
+    }
+    idx == 0 && node == root
+}
+
+/// Verify that `leaf` is included under a `Mode::DomainSeparated` root.
+///
+/// The root commits to the leaf count, so it must be supplied alongside the proof.
+pub fn verify_separated_proof<HashT: Digest>(
+    root: &[u8],
+    leaf: &[u8],
+    leaf_count: u64,
+    proof: &Proof,
+) -> bool {
+    if proof.index >= leaf_count || proof.siblings.len() != depth(leaf_count as usize) {
+        return false;
+    }
+    let mut node = prefixed_digest::<HashT>(LEAF_PREFIX, &[leaf]);
+    let mut idx = proof.index;
+    for sibling in &proof.siblings {
+        node = if idx & 1 == 0 {
+            prefixed_digest::<HashT>(NODE_PREFIX, &[&node, sibling])
+        } else {
+            prefixed_digest::<HashT>(NODE_PREFIX, &[sibling, &node])
+        };
+        idx /= 2;
+    }
+    let leaf_count = leaf_count.to_be_bytes();
+    prefixed_digest::<HashT>(ROOT_PREFIX, &[&leaf_count, &node]) == root
 }
 
 #[cfg(test)]
@@ -61,6 +305,89 @@ mod tests {
         );
     }
 
+    #[test]
+    fn proofs() {
+        let leaves: Vec<Vec<u8>> = (0..13u8).map(|i| vec![i; 32]).collect();
+        for num_leaves in 1..leaves.len() {
+            let mut merkle = MerkleTree::<blake3::Hasher>::new();
+            for leaf in &leaves[..num_leaves] {
+                merkle.add_leaf(leaf);
+            }
+            assert_eq!(merkle.proof(0), None);
+            merkle.build_tree();
+            let root = merkle.root().unwrap();
+
+            for (index, leaf) in leaves[..num_leaves].iter().enumerate() {
+                let proof = merkle.proof(index).unwrap();
+                assert_eq!(Proof::from_bytes(&proof.to_bytes()), Some(proof.clone()));
+                assert!(verify_proof::<blake3::Hasher>(root, leaf, &proof));
+
+                // Wrong leaf or position
+                let other = &leaves[num_leaves];
+                assert!(!verify_proof::<blake3::Hasher>(root, other, &proof));
+                let mut moved = proof.clone();
+                moved.index ^= 1 << proof.siblings.len();
+                assert!(!verify_proof::<blake3::Hasher>(root, leaf, &moved));
+            }
+            assert_eq!(merkle.proof(num_leaves), None);
+        }
+    }
+
+    #[test]
+    fn separated_proofs() {
+        let leaves: Vec<Vec<u8>> = (0..13u8).map(|i| vec![i; 32]).collect();
+        for num_leaves in 1..leaves.len() {
+            let mut merkle = MerkleTree::<blake3::Hasher>::with_mode(Mode::DomainSeparated);
+            for leaf in &leaves[..num_leaves] {
+                merkle.add_leaf(leaf);
+            }
+            merkle.build_tree();
+            let root = merkle.root().unwrap();
+            let leaf_count = num_leaves as u64;
+
+            for (index, leaf) in leaves[..num_leaves].iter().enumerate() {
+                let proof = merkle.proof(index).unwrap();
+                assert_eq!(Proof::from_bytes(&proof.to_bytes()), Some(proof.clone()));
+                assert!(verify_separated_proof::<blake3::Hasher>(
+                    root, leaf, leaf_count, &proof
+                ));
+                assert!(!verify_separated_proof::<blake3::Hasher>(
+                    root,
+                    leaf,
+                    leaf_count + 1,
+                    &proof
+                ));
+                assert!(!verify_separated_proof::<blake3::Hasher>(
+                    root,
+                    &leaves[num_leaves],
+                    leaf_count,
+                    &proof
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn separated_duplicate_leaf() {
+        let build = |leaves: &[&[u8]], mode: Mode| {
+            let leaves: Vec<Vec<u8>> = leaves.iter().map(|leaf| leaf.to_vec()).collect();
+            let mut merkle = MerkleTree::<blake3::Hasher>::with_mode(mode);
+            for leaf in &leaves {
+                merkle.add_leaf(leaf);
+            }
+            merkle.build_tree();
+            merkle.root().unwrap().to_vec()
+        };
+
+        // Duplicating the last leaf of an odd row collides in legacy mode only
+        let (odd, even): (&[&[u8]], &[&[u8]]) = (&[b"a", b"b", b"c"], &[b"a", b"b", b"c", b"c"]);
+        assert_eq!(build(odd, Mode::Legacy), build(even, Mode::Legacy));
+        assert_ne!(
+            build(odd, Mode::DomainSeparated),
+            build(even, Mode::DomainSeparated)
+        );
+    }
+
     fn build_and_validate<T: Digest + Default>(
         mut merkle: MerkleTree<T>,
         num_leaves: usize,
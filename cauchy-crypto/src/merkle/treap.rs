@@ -0,0 +1,295 @@
+use std::{cmp::Ordering, convert::TryInto, marker::PhantomData};
+
+use digest::Digest;
+
+type Link = Option<Box<Node>>;
+
+#[derive(Clone)]
+struct Node {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    /// Derived from the key, so the shape of the treap depends only on its contents.
+    priority: u64,
+    hash: Vec<u8>,
+    left: Link,
+    right: Link,
+}
+
+impl Node {
+    /// Compare heap priority, ties are broken by key.
+    fn outranks(&self, other: &Node) -> bool {
+        (self.priority, &self.key) > (other.priority, &other.key)
+    }
+}
+
+/// An owned Merkle tree with leaves ordered by key.
+///
+/// Internally a treap, each node caches the hash of its subtree so insertions and removals rehash only the
+/// nodes along their path, taking expected O(log n) time. The shape, and therefore the root, is independent of
+/// the order of updates.
+///
+/// A node hashes to `H(left || H(key || value) || right)`, where a missing child is `H("")`.
+pub struct MerkleTreap<HashT: Digest> {
+    root: Link,
+    len: usize,
+    _hasher: PhantomData<HashT>,
+}
+
+impl<HashT: Digest> Default for MerkleTreap<HashT> {
+    fn default() -> Self {
+        Self {
+            root: None,
+            len: 0,
+            _hasher: PhantomData,
+        }
+    }
+}
+
+impl<HashT: Digest> Clone for MerkleTreap<HashT> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            len: self.len,
+            _hasher: PhantomData,
+        }
+    }
+}
+
+impl<HashT: Digest> MerkleTreap<HashT> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The root hash, `None` if the tree is empty.
+    pub fn root(&self) -> Option<&[u8]> {
+        self.root.as_ref().map(|node| &node.hash[..])
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        let mut link = &self.root;
+        while let Some(node) = link {
+            link = match key.cmp(&node.key) {
+                Ordering::Less => &node.left,
+                Ordering::Greater => &node.right,
+                Ordering::Equal => return Some(&node.value),
+            };
+        }
+        None
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Insert a leaf, returning the previous value if the key was present.
+    pub fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) -> Option<Vec<u8>> {
+        let old = self.remove(&key);
+        let priority = u64::from_be_bytes(HashT::digest(&key)[..8].try_into().unwrap()); // This is safe
+        let mut node = Box::new(Node {
+            key,
+            value,
+            priority,
+            hash: Vec::new(),
+            left: None,
+            right: None,
+        });
+        Self::rehash(&mut node);
+        self.root = Some(Self::insert_node(self.root.take(), node));
+        self.len += 1;
+        old
+    }
+
+    /// Remove a leaf, returning its value.
+    pub fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        let node = Self::remove_node(&mut self.root, key)?;
+        self.len -= 1;
+        Some(node.value)
+    }
+
+    /// Iterate over the leaves in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        let mut stack = Vec::new();
+        let mut link = &self.root;
+        std::iter::from_fn(move || {
+            while let Some(node) = link {
+                stack.push(node);
+                link = &node.left;
+            }
+            let node = stack.pop()?;
+            link = &node.right;
+            Some((&node.key[..], &node.value[..]))
+        })
+    }
+
+    fn child_hash(link: &Link) -> Vec<u8> {
+        match link {
+            Some(node) => node.hash.clone(),
+            None => HashT::digest(&[]).to_vec(),
+        }
+    }
+
+    /// Recompute a nodes hash from its children.
+    fn rehash(node: &mut Node) {
+        let leaf_hash = HashT::digest(&[&node.key[..], &node.value[..]].concat());
+        let preimage = [
+            &Self::child_hash(&node.left)[..],
+            &leaf_hash[..],
+            &Self::child_hash(&node.right)[..],
+        ]
+        .concat();
+        node.hash = HashT::digest(&preimage).to_vec();
+    }
+
+    /// Split into nodes with keys less than `key` and the remainder.
+    fn split(link: Link, key: &[u8]) -> (Link, Link) {
+        match link {
+            None => (None, None),
+            Some(mut node) => {
+                if node.key[..] < *key {
+                    let (less, rest) = Self::split(node.right.take(), key);
+                    node.right = less;
+                    Self::rehash(&mut node);
+                    (Some(node), rest)
+                } else {
+                    let (less, rest) = Self::split(node.left.take(), key);
+                    node.left = rest;
+                    Self::rehash(&mut node);
+                    (less, Some(node))
+                }
+            }
+        }
+    }
+
+    /// Merge two treaps, every key in `left` must be less than those in `right`.
+    fn merge(left: Link, right: Link) -> Link {
+        match (left, right) {
+            (None, right) => right,
+            (left, None) => left,
+            (Some(mut left), Some(mut right)) => {
+                if left.outranks(&right) {
+                    left.right = Self::merge(left.right.take(), Some(right));
+                    Self::rehash(&mut left);
+                    Some(left)
+                } else {
+                    right.left = Self::merge(Some(left), right.left.take());
+                    Self::rehash(&mut right);
+                    Some(right)
+                }
+            }
+        }
+    }
+
+    fn insert_node(link: Link, mut new: Box<Node>) -> Box<Node> {
+        match link {
+            None => new,
+            Some(node) if new.outranks(&node) => {
+                let (less, rest) = Self::split(Some(node), &new.key);
+                new.left = less;
+                new.right = rest;
+                Self::rehash(&mut new);
+                new
+            }
+            Some(mut node) => {
+                if new.key < node.key {
+                    node.left = Some(Self::insert_node(node.left.take(), new));
+                } else {
+                    node.right = Some(Self::insert_node(node.right.take(), new));
+                }
+                Self::rehash(&mut node);
+                node
+            }
+        }
+    }
+
+    fn remove_node(link: &mut Link, key: &[u8]) -> Option<Box<Node>> {
+        let node = link.as_mut()?;
+        let removed = match key.cmp(&node.key) {
+            Ordering::Less => Self::remove_node(&mut node.left, key),
+            Ordering::Greater => Self::remove_node(&mut node.right, key),
+            Ordering::Equal => {
+                let mut removed = link.take().unwrap(); // This is safe
+                *link = Self::merge(removed.left.take(), removed.right.take());
+                return Some(removed);
+            }
+        };
+        if removed.is_some() {
+            Self::rehash(node);
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::prelude::*;
+
+    use super::*;
+
+    type Treap = MerkleTreap<blake3::Hasher>;
+
+    fn keys(n: u32) -> Vec<Vec<u8>> {
+        (0..n)
+            .map(|i| blake3::hash(&i.to_be_bytes()).as_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn order_independent() {
+        let mut keys = keys(200);
+        let mut treap_a = Treap::new();
+        for key in &keys {
+            treap_a.insert(key.clone(), key.clone());
+        }
+
+        keys.shuffle(&mut thread_rng());
+        let mut treap_b = Treap::new();
+        for key in &keys {
+            treap_b.insert(key.clone(), key.clone());
+        }
+        assert_eq!(treap_a.root(), treap_b.root());
+        assert_eq!(treap_a.len(), keys.len());
+
+        // Leaves are iterated in key order
+        keys.sort();
+        let iterated: Vec<_> = treap_a.iter().map(|(key, _)| key.to_vec()).collect();
+        assert_eq!(iterated, keys);
+    }
+
+    #[test]
+    fn update_remove() {
+        let keys = keys(50);
+        let mut treap = Treap::new();
+        assert_eq!(treap.root(), None);
+        for key in &keys[1..] {
+            treap.insert(key.clone(), key.clone());
+        }
+        let root = treap.root().unwrap().to_vec();
+
+        // Adding then removing a leaf restores the root
+        assert_eq!(treap.insert(keys[0].clone(), b"value".to_vec()), None);
+        assert_ne!(treap.root().unwrap(), &root[..]);
+        assert_eq!(treap.get(&keys[0]), Some(&b"value"[..]));
+        assert_eq!(treap.remove(&keys[0]), Some(b"value".to_vec()));
+        assert_eq!(treap.root().unwrap(), &root[..]);
+
+        // Updating a value changes the root
+        let old = treap.insert(keys[1].clone(), b"value".to_vec());
+        assert_eq!(old, Some(keys[1].clone()));
+        assert_ne!(treap.root().unwrap(), &root[..]);
+        assert_eq!(treap.len(), keys.len() - 1);
+
+        for key in &keys[1..] {
+            assert!(treap.remove(key).is_some());
+        }
+        assert!(treap.is_empty());
+        assert_eq!(treap.root(), None);
+    }
+}
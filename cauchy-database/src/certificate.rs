@@ -0,0 +1,107 @@
+use bytes::Bytes;
+
+use common::network::RoundId;
+
+use super::*;
+
+const CERTIFICATE_PREFIX: u8 = b'r';
+
+/// Number of rounds for which certificates are retained.
+pub const CERTIFICATE_RETENTION: RoundId = 1 << 16;
+
+fn certificate_key(round: RoundId) -> Vec<u8> {
+    [&[CERTIFICATE_PREFIX][..], &round.to_be_bytes()].concat()
+}
+
+/// Store the encoded certificate of a round.
+///
+/// Certificates older than `CERTIFICATE_RETENTION` rounds before it are deleted.
+pub struct PutCertificate(pub RoundId, pub Bytes);
+
+impl<B: StorageBackend> Service<PutCertificate> for Database<B> {
+    type Response = ();
+    type Error = Error;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, PutCertificate(round, certificate): PutCertificate) -> Self::Future {
+        self.blocking(move |backend| {
+            let cutoff = certificate_key(round.saturating_sub(CERTIFICATE_RETENTION));
+            let mut batch = Batch::default();
+            backend.scan_from(&certificate_key(0), &mut |key, _| {
+                if key >= &cutoff[..] {
+                    return false;
+                }
+                batch.delete(key);
+                true
+            });
+            batch.put(&certificate_key(round), &certificate);
+            backend.write(batch)
+        })
+    }
+}
+
+/// Fetch the encoded certificate of a round.
+pub struct GetCertificate(pub RoundId);
+
+impl<B: StorageBackend> Service<GetCertificate> for Database<B> {
+    type Response = Option<Bytes>;
+    type Error = Error;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, GetCertificate(round): GetCertificate) -> Self::Future {
+        self.blocking(move |backend| {
+            backend
+                .get(&certificate_key(round))
+                .map(|opt| opt.map(Bytes::from))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_util::ServiceExt;
+
+    #[tokio::test]
+    async fn by_round() {
+        let database = Database::new(MemoryBackend::default());
+
+        database
+            .clone()
+            .oneshot(PutCertificate(3, Bytes::from_static(b"three")))
+            .await
+            .unwrap();
+
+        let certificate = database.clone().oneshot(GetCertificate(3)).await.unwrap();
+        assert_eq!(certificate, Some(Bytes::from_static(b"three")));
+        let certificate = database.clone().oneshot(GetCertificate(4)).await.unwrap();
+        assert_eq!(certificate, None);
+
+        // Certificates beyond the retention period are pruned
+        database
+            .clone()
+            .oneshot(PutCertificate(4, Bytes::from_static(b"four")))
+            .await
+            .unwrap();
+        database
+            .clone()
+            .oneshot(PutCertificate(
+                4 + CERTIFICATE_RETENTION,
+                Bytes::from_static(b"latest"),
+            ))
+            .await
+            .unwrap();
+        let certificate = database.clone().oneshot(GetCertificate(3)).await.unwrap();
+        assert_eq!(certificate, None);
+        let certificate = database.oneshot(GetCertificate(4)).await.unwrap();
+        assert_eq!(certificate, Some(Bytes::from_static(b"four")));
+    }
+}
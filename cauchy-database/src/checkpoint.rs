@@ -0,0 +1,201 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use super::*;
+
+const CHECKPOINT_KEY: [u8; 1] = [b's'];
+
+const SITE_LEN: usize = 32;
+
+/// Maximum number of deferred transactions recorded in a checkpoint.
+pub const MAX_DEFERRED: usize = 1 << 16;
+
+/// A snapshot of the player state, restored on startup.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    pub oddsketch: Bytes,
+    pub minisketch: Bytes,
+    pub root: Bytes,
+    pub site: [u8; SITE_LEN],
+    pub nonce: u64,
+    /// Stored transactions held out of the mempool until the following round, at most `MAX_DEFERRED`.
+    pub deferred: Vec<TxId>,
+}
+
+impl Checkpoint {
+    fn encode(&self) -> Vec<u8> {
+        let mut raw = BytesMut::with_capacity(
+            4 + self.oddsketch.len()
+                + 4
+                + self.minisketch.len()
+                + 4
+                + self.root.len()
+                + SITE_LEN
+                + 8
+                + 4
+                + self.deferred.len() * TX_ID_LEN,
+        );
+        for field in &[&self.oddsketch, &self.minisketch, &self.root] {
+            raw.put_u32(field.len() as u32);
+            raw.put_slice(field);
+        }
+        raw.put_slice(&self.site);
+        raw.put_u64(self.nonce);
+        raw.put_u32(self.deferred.len() as u32);
+        for tx_id in &self.deferred {
+            raw.put_slice(tx_id.as_ref());
+        }
+        raw.to_vec()
+    }
+
+    fn decode(raw: &[u8]) -> Result<Self, Error> {
+        let mut raw = Bytes::copy_from_slice(raw);
+        let mut fields = Vec::with_capacity(3);
+        for _ in 0..3 {
+            if raw.remaining() < 4 {
+                return Err(Error::Corrupted);
+            }
+            let len = raw.get_u32() as usize;
+            if raw.remaining() < len {
+                return Err(Error::Corrupted);
+            }
+            fields.push(raw.split_to(len));
+        }
+        if raw.remaining() < SITE_LEN + 8 {
+            return Err(Error::Corrupted);
+        }
+        let mut site = [0; SITE_LEN];
+        raw.copy_to_slice(&mut site);
+        let nonce = raw.get_u64();
+
+        if raw.remaining() < 4 {
+            return Err(Error::Corrupted);
+        }
+        let n_deferred = raw.get_u32() as usize;
+        if n_deferred > MAX_DEFERRED || raw.remaining() != n_deferred * TX_ID_LEN {
+            return Err(Error::Corrupted);
+        }
+        let mut deferred = Vec::with_capacity(n_deferred);
+        for _ in 0..n_deferred {
+            let mut tx_id = [0; TX_ID_LEN];
+            raw.copy_to_slice(&mut tx_id);
+            deferred.push(TxId(tx_id));
+        }
+
+        let root = fields.pop().unwrap(); // This is safe
+        let minisketch = fields.pop().unwrap(); // This is safe
+        let oddsketch = fields.pop().unwrap(); // This is safe
+        Ok(Self {
+            oddsketch,
+            minisketch,
+            root,
+            site,
+            nonce,
+            deferred,
+        })
+    }
+}
+
+/// Store a checkpoint, replacing the previous one.
+pub struct PutCheckpoint(pub Checkpoint);
+
+impl<B: StorageBackend> Service<PutCheckpoint> for Database<B> {
+    type Response = ();
+    type Error = Error;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, PutCheckpoint(checkpoint): PutCheckpoint) -> Self::Future {
+        self.blocking(move |backend| backend.put(&CHECKPOINT_KEY, &checkpoint.encode()))
+    }
+}
+
+/// Fetch the latest checkpoint.
+pub struct GetCheckpoint;
+
+impl<B: StorageBackend> Service<GetCheckpoint> for Database<B> {
+    type Response = Option<Checkpoint>;
+    type Error = Error;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _: GetCheckpoint) -> Self::Future {
+        self.blocking(move |backend| {
+            backend
+                .get(&CHECKPOINT_KEY)?
+                .map(|raw| Checkpoint::decode(&raw))
+                .transpose()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_util::ServiceExt;
+
+    #[tokio::test]
+    async fn put_get() {
+        let database = Database::new(MemoryBackend::default());
+        let fetched = database.clone().oneshot(GetCheckpoint).await.unwrap();
+        assert_eq!(fetched, None);
+
+        let checkpoint = Checkpoint {
+            oddsketch: Bytes::from(vec![1; 16]),
+            minisketch: Bytes::from(vec![2; 24]),
+            root: Bytes::from(vec![3; 32]),
+            site: [4; SITE_LEN],
+            nonce: 5,
+            deferred: vec![TxId([6; TX_ID_LEN]), TxId([7; TX_ID_LEN])],
+        };
+        database
+            .clone()
+            .oneshot(PutCheckpoint(checkpoint.clone()))
+            .await
+            .unwrap();
+        let fetched = database.clone().oneshot(GetCheckpoint).await.unwrap();
+        assert_eq!(fetched, Some(checkpoint.clone()));
+    }
+
+    #[test]
+    fn decode_exact() {
+        let checkpoint = Checkpoint {
+            oddsketch: Bytes::from(vec![1; 16]),
+            minisketch: Bytes::from(vec![2; 24]),
+            root: Bytes::from(vec![3; 32]),
+            site: [4; SITE_LEN],
+            nonce: 5,
+            deferred: vec![TxId([6; TX_ID_LEN])],
+        };
+        let raw = checkpoint.encode();
+        assert_eq!(Checkpoint::decode(&raw).unwrap(), checkpoint);
+
+        // Truncated or trailing bytes are rejected
+        for len in 0..raw.len() {
+            assert!(matches!(
+                Checkpoint::decode(&raw[..len]),
+                Err(Error::Corrupted)
+            ));
+        }
+        let trailing = [&raw[..], &[0]].concat();
+        assert!(matches!(
+            Checkpoint::decode(&trailing),
+            Err(Error::Corrupted)
+        ));
+
+        // The deferred count is capped
+        let deferred_at = raw.len() - 4 - TX_ID_LEN;
+        let mut oversized = raw[..deferred_at].to_vec();
+        oversized.extend_from_slice(&(MAX_DEFERRED as u32 + 1).to_be_bytes());
+        oversized.extend(vec![0; (MAX_DEFERRED + 1) * TX_ID_LEN]);
+        assert!(matches!(
+            Checkpoint::decode(&oversized),
+            Err(Error::Corrupted)
+        ));
+    }
+}
@@ -0,0 +1,154 @@
+use bytes::Bytes;
+
+use super::*;
+
+const CONTRACT_PREFIX: u8 = b'c';
+
+pub(crate) fn contract_key(contract_id: &[u8]) -> Vec<u8> {
+    [&[CONTRACT_PREFIX][..], contract_id].concat()
+}
+
+/// Store the state of a contract, keyed by the ID of its deploying transaction.
+pub struct PutContractState(pub TxId, pub Bytes);
+
+impl<B: StorageBackend> Service<PutContractState> for Database<B> {
+    type Response = ();
+    type Error = Error;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, PutContractState(contract_id, state): PutContractState) -> Self::Future {
+        self.blocking(move |backend| backend.put(&contract_key(contract_id.as_ref()), &state))
+    }
+}
+
+/// Store a transaction along with the contract state resulting from its execution, atomically.
+pub struct PutExecution {
+    pub tx: Transaction,
+    /// ID of the transaction which deployed the contract.
+    pub contract_id: TxId,
+    pub state: Bytes,
+}
+
+impl<B: StorageBackend> Service<PutExecution> for Database<B> {
+    type Response = ();
+    type Error = Error;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, execution: PutExecution) -> Self::Future {
+        self.blocking(move |backend| {
+            let mut batch = Batch::default();
+            put_tx(&mut batch, &execution.tx);
+            batch.put(
+                &contract_key(execution.contract_id.as_ref()),
+                &execution.state,
+            );
+            backend.write(batch)
+        })
+    }
+}
+
+/// Fetch the state of a contract.
+pub struct GetContractState(pub TxId);
+
+impl<B: StorageBackend> Service<GetContractState> for Database<B> {
+    type Response = Option<Bytes>;
+    type Error = Error;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, GetContractState(contract_id): GetContractState) -> Self::Future {
+        self.blocking(move |backend| {
+            backend
+                .get(&contract_key(contract_id.as_ref()))
+                .map(|opt| opt.map(Bytes::from))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_util::ServiceExt;
+
+    #[tokio::test]
+    async fn isolated() {
+        let database = Database::new(MemoryBackend::default());
+        let (id_a, id_b) = (TxId([1; TX_ID_LEN]), TxId([2; TX_ID_LEN]));
+
+        database
+            .clone()
+            .oneshot(PutContractState(id_a, Bytes::from_static(b"a")))
+            .await
+            .unwrap();
+        database
+            .clone()
+            .oneshot(PutContractState(id_b, Bytes::from_static(b"b")))
+            .await
+            .unwrap();
+
+        let state_a = database
+            .clone()
+            .oneshot(GetContractState(id_a))
+            .await
+            .unwrap();
+        assert_eq!(state_a, Some(Bytes::from_static(b"a")));
+        let state_c = database
+            .oneshot(GetContractState(TxId([3; TX_ID_LEN])))
+            .await
+            .unwrap();
+        assert_eq!(state_c, None);
+    }
+
+    #[tokio::test]
+    async fn execution() {
+        let database = Database::new(MemoryBackend::default());
+        let tx = Transaction {
+            timestamp: 1,
+            binary: Bytes::from_static(b"binary"),
+            aux_data: Bytes::new(),
+        };
+        let tx_id = tx.get_id();
+
+        database
+            .clone()
+            .oneshot(PutExecution {
+                tx: tx.clone(),
+                contract_id: tx_id,
+                state: Bytes::from_static(b"state"),
+            })
+            .await
+            .unwrap();
+        let fetched = database
+            .clone()
+            .oneshot(GetTransaction(tx_id))
+            .await
+            .unwrap();
+        assert_eq!(fetched, Some(tx));
+        let state = database
+            .clone()
+            .oneshot(GetContractState(tx_id))
+            .await
+            .unwrap();
+        assert_eq!(state, Some(Bytes::from_static(b"state")));
+
+        // Deleting the deployment removes the contract state with it
+        database
+            .clone()
+            .oneshot(DeleteTransaction(tx_id))
+            .await
+            .unwrap();
+        let state = database.oneshot(GetContractState(tx_id)).await.unwrap();
+        assert_eq!(state, None);
+    }
+}
@@ -1,11 +1,343 @@
-// use std::{pin::Pin, sync::Arc};
+mod certificate;
+mod checkpoint;
+mod contract;
+mod memory;
+mod rocks;
 
-// use tower_service::Service;
+pub use certificate::*;
+pub use checkpoint::*;
+pub use contract::*;
+pub use memory::MemoryBackend;
+pub use rocks::RocksBackend;
 
-#[derive(Clone, Default)]
-pub struct Database {}
+use std::{convert::TryInto, fmt, ops::RangeInclusive, path::Path};
 
+use bytes::{Bytes, BytesMut};
+use futures_core::task::{Context, Poll};
+use tower_service::Service;
+
+use common::{
+    network::{Transaction, TxId, TX_ID_LEN},
+    FutResponse,
+};
+
+/*
+Key layout:
+    't' || tx_id                -> transaction
+    'i' || timestamp || tx_id   -> empty (timestamp index)
+    's'                         -> checkpoint
+    'c' || tx_id                -> contract state
+    'r' || round                -> round certificate
+*/
+
+const TX_PREFIX: u8 = b't';
+const TIMESTAMP_PREFIX: u8 = b'i';
+
+/// An error associated with the `Database`.
 #[derive(Debug)]
 pub enum Error {
-    Rocks,
+    /// An error raised by the storage backend.
+    Backend(Box<dyn std::error::Error + Send + Sync>),
+    /// A stored value could not be decoded.
+    Corrupted,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Backend(err) => err.fmt(f),
+            Self::Corrupted => writeln!(f, "corrupted value"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A key-value storage backend.
+///
+/// Calls are blocking, the `Database` is responsible for moving them off the executor.
+pub trait StorageBackend: Clone + Send + Sync + 'static {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error>;
+
+    fn delete(&self, key: &[u8]) -> Result<(), Error>;
+
+    /// Apply a batch of writes atomically.
+    fn write(&self, batch: Batch) -> Result<(), Error>;
+
+    /// Visit key-value pairs in key order, starting at `start`, until `f` returns `false`.
+    fn scan_from(&self, start: &[u8], f: &mut dyn FnMut(&[u8], &[u8]) -> bool);
+}
+
+/// A write to a `StorageBackend`.
+pub enum BatchOp {
+    Put(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+/// A set of writes, applied atomically by `StorageBackend::write`.
+#[derive(Default)]
+pub struct Batch(pub Vec<BatchOp>);
+
+impl Batch {
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.0.push(BatchOp::Put(key.to_vec(), value.to_vec()));
+    }
+
+    pub fn delete(&mut self, key: &[u8]) {
+        self.0.push(BatchOp::Delete(key.to_vec()));
+    }
+}
+
+/// A transaction store exposed as services over a `StorageBackend`.
+#[derive(Clone)]
+pub struct Database<B = RocksBackend> {
+    backend: B,
+}
+
+impl Database<RocksBackend> {
+    /// Open an on-disk database at `path`, creating it if missing.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        RocksBackend::open(path).map(Self::new)
+    }
+}
+
+impl<B: StorageBackend> Database<B> {
+    /// Construct a new `Database` from a backend.
+    pub fn new(backend: B) -> Self {
+        Self { backend }
+    }
+
+    /// Run a blocking closure against the backend.
+    fn blocking<F, T>(&self, f: F) -> FutResponse<T, Error>
+    where
+        F: FnOnce(&B) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let backend = self.backend.clone();
+        let fut = async move {
+            tokio::task::spawn_blocking(move || f(&backend))
+                .await
+                .unwrap() // This is safe
+        };
+        Box::pin(fut)
+    }
+}
+
+fn tx_key(tx_id: &[u8]) -> Vec<u8> {
+    [&[TX_PREFIX][..], tx_id].concat()
+}
+
+fn timestamp_key(timestamp: u64, tx_id: &[u8]) -> Vec<u8> {
+    [&[TIMESTAMP_PREFIX][..], &timestamp.to_be_bytes(), tx_id].concat()
+}
+
+fn encode_tx(tx: &Transaction) -> Vec<u8> {
+    let mut raw = BytesMut::with_capacity(tx.encoded_len());
+    tx.encode(&mut raw);
+    raw.to_vec()
+}
+
+fn decode_tx(raw: &[u8]) -> Result<Transaction, Error> {
+    Transaction::decode(Bytes::copy_from_slice(raw)).map_err(|_| Error::Corrupted)
+}
+
+fn get_tx<B: StorageBackend>(backend: &B, tx_id: &[u8]) -> Result<Option<Transaction>, Error> {
+    backend
+        .get(&tx_key(tx_id))?
+        .map(|raw| decode_tx(&raw))
+        .transpose()
+}
+
+fn put_tx(batch: &mut Batch, tx: &Transaction) {
+    let tx_id = tx.get_id();
+    batch.put(&tx_key(tx_id.as_ref()), &encode_tx(tx));
+    batch.put(&timestamp_key(tx.timestamp, tx_id.as_ref()), &[]);
+}
+
+/// Store a transaction, indexed by its ID and timestamp.
+pub struct PutTransaction(pub Transaction);
+
+impl<B: StorageBackend> Service<PutTransaction> for Database<B> {
+    type Response = ();
+    type Error = Error;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, PutTransaction(tx): PutTransaction) -> Self::Future {
+        self.blocking(move |backend| {
+            let mut batch = Batch::default();
+            put_tx(&mut batch, &tx);
+            backend.write(batch)
+        })
+    }
+}
+
+/// Fetch a transaction by ID.
+pub struct GetTransaction(pub TxId);
+
+impl<B: StorageBackend> Service<GetTransaction> for Database<B> {
+    type Response = Option<Transaction>;
+    type Error = Error;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, GetTransaction(tx_id): GetTransaction) -> Self::Future {
+        self.blocking(move |backend| get_tx(backend, tx_id.as_ref()))
+    }
+}
+
+/// Delete a transaction by ID, along with the state of the contract it deployed.
+pub struct DeleteTransaction(pub TxId);
+
+impl<B: StorageBackend> Service<DeleteTransaction> for Database<B> {
+    type Response = ();
+    type Error = Error;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, DeleteTransaction(tx_id): DeleteTransaction) -> Self::Future {
+        self.blocking(move |backend| {
+            if let Some(tx) = get_tx(backend, tx_id.as_ref())? {
+                let mut batch = Batch::default();
+                batch.delete(&timestamp_key(tx.timestamp, tx_id.as_ref()));
+                batch.delete(&tx_key(tx_id.as_ref()));
+                batch.delete(&contract_key(tx_id.as_ref()));
+                backend.write(batch)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Fetch all transactions with timestamps in the range, ordered by timestamp.
+pub struct TransactionsByTime(pub RangeInclusive<u64>);
+
+impl<B: StorageBackend> Service<TransactionsByTime> for Database<B> {
+    type Response = Vec<Transaction>;
+    type Error = Error;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, TransactionsByTime(range): TransactionsByTime) -> Self::Future {
+        self.blocking(move |backend| {
+            let start = [&[TIMESTAMP_PREFIX][..], &range.start().to_be_bytes()].concat();
+
+            // Collect IDs from the timestamp index
+            let mut tx_ids = Vec::new();
+            backend.scan_from(&start, &mut |key, _| {
+                if key.len() != 1 + 8 + TX_ID_LEN || key[0] != TIMESTAMP_PREFIX {
+                    return false;
+                }
+                let timestamp = u64::from_be_bytes(key[1..9].try_into().unwrap()); // This is safe
+                if timestamp > *range.end() {
+                    return false;
+                }
+                tx_ids.push(key[9..].to_vec());
+                true
+            });
+
+            tx_ids
+                .iter()
+                .map(|tx_id| get_tx(backend, tx_id)?.ok_or(Error::Corrupted))
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_util::ServiceExt;
+
+    fn tx(timestamp: u64, binary: &'static [u8]) -> Transaction {
+        Transaction {
+            timestamp,
+            binary: Bytes::from_static(binary),
+            aux_data: Bytes::from_static(b"aux"),
+        }
+    }
+
+    async fn put_get_delete<B: StorageBackend>(database: Database<B>) {
+        let tx = tx(1, b"binary");
+        let tx_id = tx.get_id();
+        database
+            .clone()
+            .oneshot(PutTransaction(tx.clone()))
+            .await
+            .unwrap();
+        let fetched = database
+            .clone()
+            .oneshot(GetTransaction(tx_id))
+            .await
+            .unwrap();
+        assert_eq!(fetched, Some(tx));
+
+        database
+            .clone()
+            .oneshot(DeleteTransaction(tx_id))
+            .await
+            .unwrap();
+        let fetched = database.oneshot(GetTransaction(tx_id)).await.unwrap();
+        assert_eq!(fetched, None);
+    }
+
+    async fn by_time<B: StorageBackend>(database: Database<B>) {
+        let txs = vec![tx(3, b"c"), tx(1, b"a"), tx(2, b"b"), tx(5, b"d")];
+        for tx in &txs {
+            database
+                .clone()
+                .oneshot(PutTransaction(tx.clone()))
+                .await
+                .unwrap();
+        }
+
+        let fetched = database
+            .clone()
+            .oneshot(TransactionsByTime(2..=3))
+            .await
+            .unwrap();
+        assert_eq!(fetched, vec![txs[2].clone(), txs[0].clone()]);
+
+        let fetched = database
+            .oneshot(TransactionsByTime(0..=std::u64::MAX))
+            .await
+            .unwrap();
+        assert_eq!(fetched.len(), txs.len());
+    }
+
+    #[tokio::test]
+    async fn put_get_delete_rocks() {
+        let dir = tempfile::tempdir().unwrap();
+        put_get_delete(Database::open(dir.path()).unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn put_get_delete_memory() {
+        put_get_delete(Database::new(MemoryBackend::default())).await
+    }
+
+    #[tokio::test]
+    async fn by_time_rocks() {
+        let dir = tempfile::tempdir().unwrap();
+        by_time(Database::open(dir.path()).unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn by_time_memory() {
+        by_time(Database::new(MemoryBackend::default())).await
+    }
 }
@@ -0,0 +1,54 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use super::*;
+
+/// A volatile in-memory backend. Clones share the same store.
+#[derive(Clone, Default)]
+pub struct MemoryBackend {
+    map: Arc<RwLock<BTreeMap<Vec<u8>, Vec<u8>>>>,
+}
+
+impl StorageBackend for MemoryBackend {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.map.read().unwrap().get(key).cloned())
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.map
+            .write()
+            .unwrap()
+            .insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<(), Error> {
+        self.map.write().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn write(&self, batch: Batch) -> Result<(), Error> {
+        let mut map = self.map.write().unwrap();
+        for op in batch.0 {
+            match op {
+                BatchOp::Put(key, value) => {
+                    map.insert(key, value);
+                }
+                BatchOp::Delete(key) => {
+                    map.remove(&key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn scan_from(&self, start: &[u8], f: &mut dyn FnMut(&[u8], &[u8]) -> bool) {
+        for (key, value) in self.map.read().unwrap().range(start.to_vec()..) {
+            if !f(key, value) {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use std::{path::Path, sync::Arc};
+
+use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+
+use super::*;
+
+/// An on-disk backend using RocksDB.
+#[derive(Clone)]
+pub struct RocksBackend {
+    db: Arc<DB>,
+}
+
+fn backend_error(err: rocksdb::Error) -> Error {
+    Error::Backend(Box::new(err))
+}
+
+impl RocksBackend {
+    /// Open the store at `path`, creating it if missing.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let db = DB::open_default(path).map_err(backend_error)?;
+        Ok(Self { db: Arc::new(db) })
+    }
+}
+
+impl StorageBackend for RocksBackend {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        self.db.get(key).map_err(backend_error)
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.db.put(key, value).map_err(backend_error)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<(), Error> {
+        self.db.delete(key).map_err(backend_error)
+    }
+
+    fn write(&self, batch: Batch) -> Result<(), Error> {
+        let mut rocks_batch = WriteBatch::default();
+        for op in batch.0 {
+            match op {
+                BatchOp::Put(key, value) => rocks_batch.put(key, value),
+                BatchOp::Delete(key) => rocks_batch.delete(key),
+            }
+        }
+        self.db.write(rocks_batch).map_err(backend_error)
+    }
+
+    fn scan_from(&self, start: &[u8], f: &mut dyn FnMut(&[u8], &[u8]) -> bool) {
+        for (key, value) in self
+            .db
+            .iterator(IteratorMode::From(start, Direction::Forward))
+        {
+            if !f(&key, &value) {
+                break;
+            }
+        }
+    }
+}
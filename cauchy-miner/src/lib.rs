@@ -136,18 +136,30 @@ impl MiningCoordinator {
     }
 }
 
-pub struct NewSession(pub RawSite);
-
-impl Service<NewSession> for MiningCoordinator {
-    type Response = Arc<AtomicU64>;
-    type Error = ();
-    type Future = FutResponse<Self::Response, Self::Error>;
+/// Derive the site a player mines at from its public key and state root.
+pub fn site(pubkey: &[u8], root: &[u8]) -> RawSite {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(pubkey);
+    hasher.update(root);
+    *hasher.finalize().as_bytes()
+}
 
-    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
-    }
+/// Hash a nonce at a site.
+pub fn digest(site: &RawSite, nonce: u64) -> Digest {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(site);
+    hasher.update(&nonce.to_be_bytes());
+    *hasher.finalize().as_bytes()
+}
 
-    fn call(&mut self, NewSession(site): NewSession) -> Self::Future {
+impl MiningCoordinator {
+    /// Stop the current workers and start mining at a site, seeded with a best nonce and digest.
+    fn start_session(
+        &mut self,
+        site: RawSite,
+        best_nonce: u64,
+        best_digest: Digest,
+    ) -> FutResponse<Arc<AtomicU64>, ()> {
         // Stop workers
         let mut terminators = self.terminators.lock();
         while let Some(atomic) = terminators.pop() {
@@ -155,9 +167,10 @@ impl Service<NewSession> for MiningCoordinator {
         }
 
         // Create miner
-        let best_nonce = Arc::new(AtomicU64::new(0));
-        let best_digest = Arc::new(Mutex::new(WORST_DIGEST));
+        let best_nonce = Arc::new(AtomicU64::new(best_nonce));
+        let best_digest = Arc::new(Mutex::new(best_digest));
         let terminator = Arc::new(AtomicBool::new(false));
+        terminators.push(terminator.clone());
         let miner = Miner::new(
             site,
             best_nonce.clone(),
@@ -181,3 +194,37 @@ impl Service<NewSession> for MiningCoordinator {
         })
     }
 }
+
+pub struct NewSession(pub RawSite);
+
+impl Service<NewSession> for MiningCoordinator {
+    type Response = Arc<AtomicU64>;
+    type Error = ();
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, NewSession(site): NewSession) -> Self::Future {
+        self.start_session(site, 0, WORST_DIGEST)
+    }
+}
+
+/// Resume mining at a site, seeded with a previously found nonce.
+pub struct ResumeSession(pub RawSite, pub u64);
+
+impl Service<ResumeSession> for MiningCoordinator {
+    type Response = Arc<AtomicU64>;
+    type Error = ();
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, ResumeSession(site, nonce): ResumeSession) -> Self::Future {
+        let best_digest = digest(&site, nonce);
+        self.start_session(site, nonce, best_digest)
+    }
+}
@@ -8,7 +8,11 @@ It's therefore possible that this is a few allocations short of perfect on some
 However, it is very possible that the compiler is compiling the problem away.
 */
 
-use std::io;
+use std::{
+    convert::TryInto,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
 
 use bytes::BytesMut;
 use bytes::{buf::Buf, Bytes};
@@ -18,10 +22,85 @@ use tracing::trace;
 use super::*;
 use common::network::*;
 
+/// Returns an error if `len` exceeds `limit`.
+fn check_limit(field: &'static str, len: usize, limit: usize) -> Result<(), DecodeError> {
+    if len > limit {
+        Err(DecodeError::Oversized { field, len, limit })
+    } else {
+        Ok(())
+    }
+}
+
 /*
 Decoding states
 */
 
+#[derive(Debug, Default)]
+pub struct HelloState {
+    /// Version, features and public key length.
+    header: Option<(u32, u64, u16)>,
+}
+
+impl HelloState {
+    fn decode(
+        &mut self,
+        src: &mut BytesMut,
+        limits: &Limits,
+    ) -> Result<Option<Hello>, DecodeError> {
+        if self.header.is_none() {
+            if src.remaining() < MAGIC_BYTES.len() + 4 + 8 + 2 {
+                return Ok(None);
+            }
+            let magic_bytes = src.split_to(MAGIC_BYTES.len());
+            if magic_bytes[..] != MAGIC_BYTES {
+                return Err(DecodeError::MagicBytes);
+            }
+            let (version, features, pubkey_len) = (src.get_u32(), src.get_u64(), src.get_u16());
+            check_limit("pubkey", pubkey_len as usize, limits.max_pubkey_len)?;
+            self.header = Some((version, features, pubkey_len));
+        }
+        let (version, features, pubkey_len) = self.header.unwrap(); // This is safe
+        let pubkey_len = pubkey_len as usize;
+
+        // Peek at the address family
+        if src.remaining() < pubkey_len + 1 {
+            return Ok(None);
+        }
+        let ip_len = match src[pubkey_len] {
+            4 => 4,
+            6 => 16,
+            _ => return Err(DecodeError::InvalidAddress),
+        };
+        if src.remaining() < pubkey_len + 1 + ip_len + 2 + 4 + 8 {
+            return Ok(None);
+        }
+
+        let pubkey = src.split_to(pubkey_len).freeze();
+        src.advance(1);
+        let raw_ip = src.split_to(ip_len);
+        let ip = if ip_len == 4 {
+            let octets: [u8; 4] = raw_ip[..].try_into().unwrap(); // This is safe
+            IpAddr::V4(Ipv4Addr::from(octets))
+        } else {
+            let octets: [u8; 16] = raw_ip[..].try_into().unwrap(); // This is safe
+            IpAddr::V6(Ipv6Addr::from(octets))
+        };
+        let port = src.get_u16();
+        let radius = src.get_u32();
+        let nonce = src.get_u64();
+
+        let hello = Hello {
+            version,
+            features,
+            pubkey,
+            addr: SocketAddr::new(ip, port),
+            radius,
+            nonce,
+        };
+        Ok(Some(hello))
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct StatusState {
     oddsketch_len: Option<u16>,
@@ -29,27 +108,42 @@ pub struct StatusState {
 
 impl StatusState {
     fn decode_inner(&mut self, src: &mut BytesMut) -> Option<Status> {
-        if src.remaining() < (self.oddsketch_len.unwrap() + DIGEST_LEN as u16 + 8) as usize {
+        if src.remaining()
+            < self.oddsketch_len.unwrap() as usize + DIGEST_LEN + 8 + 8 + SIGNATURE_LEN
+        {
             None
         } else {
             let oddsketch = src.split_to(self.oddsketch_len.unwrap() as usize).freeze();
             let root = src.split_to(DIGEST_LEN).freeze();
             let nonce = src.get_u64();
+            let round = src.get_u64();
+            let signature = src.split_to(SIGNATURE_LEN).freeze();
             let status = Status {
                 oddsketch,
                 root,
                 nonce,
+                round,
+                signature,
             };
             Some(status)
         }
     }
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Status>, DecodeError> {
+    fn decode(
+        &mut self,
+        src: &mut BytesMut,
+        limits: &Limits,
+    ) -> Result<Option<Status>, DecodeError> {
         if self.oddsketch_len.is_none() {
             if src.remaining() < 2 {
                 Ok(None)
             } else {
                 let oddsketch_len = src.get_u16();
+                check_limit(
+                    "oddsketch",
+                    oddsketch_len as usize,
+                    limits.max_oddsketch_len,
+                )?;
                 self.oddsketch_len = Some(oddsketch_len);
 
                 Ok(self.decode_inner(src))
@@ -69,37 +163,51 @@ pub struct TransactionState {
 }
 
 impl TransactionState {
-    fn decode_with_timestamp(&mut self, src: &mut BytesMut) -> Option<Transaction> {
+    fn decode_with_timestamp(
+        &mut self,
+        src: &mut BytesMut,
+        limits: &Limits,
+    ) -> Result<Option<Transaction>, DecodeError> {
         if src.remaining() < 4 {
-            None
+            Ok(None)
         } else {
             let binary_len = src.get_u32();
+            check_limit("binary", binary_len as usize, limits.max_binary_len)?;
             self.binary_len = Some(binary_len);
 
-            self.decode_with_binary_len(src)
+            self.decode_with_binary_len(src, limits)
         }
     }
 
-    fn decode_with_binary_len(&mut self, src: &mut BytesMut) -> Option<Transaction> {
+    fn decode_with_binary_len(
+        &mut self,
+        src: &mut BytesMut,
+        limits: &Limits,
+    ) -> Result<Option<Transaction>, DecodeError> {
         let binary_len_usize = self.binary_len.unwrap() as usize;
         if src.remaining() < binary_len_usize {
-            None
+            Ok(None)
         } else {
             let binary = src.split_to(binary_len_usize).freeze();
             self.binary = Some(binary);
 
-            self.decode_with_binary(src)
+            self.decode_with_binary(src, limits)
         }
     }
 
-    fn decode_with_binary(&mut self, src: &mut BytesMut) -> Option<Transaction> {
+    fn decode_with_binary(
+        &mut self,
+        src: &mut BytesMut,
+        limits: &Limits,
+    ) -> Result<Option<Transaction>, DecodeError> {
         if src.remaining() < 4 {
-            None
+            Ok(None)
         } else {
             let aux_len = src.get_u32();
+            check_limit("aux_data", aux_len as usize, limits.max_aux_len)?;
             self.aux_len = Some(aux_len);
 
-            self.decode_with_aux_len(src)
+            Ok(self.decode_with_aux_len(src))
         }
     }
 
@@ -119,28 +227,35 @@ impl TransactionState {
         }
     }
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Transaction>, DecodeError> {
-        let tx = match (
+    fn decode(
+        &mut self,
+        src: &mut BytesMut,
+        limits: &Limits,
+    ) -> Result<Option<Transaction>, DecodeError> {
+        match (
             self.timestamp.is_some(),
             self.binary_len.is_some(),
             self.binary.is_some(),
             self.aux_len.is_some(),
         ) {
             (false, _, _, _) => {
-                if src.remaining() < 8 {
-                    None
+                if src.remaining() < 1 + 8 {
+                    Ok(None)
                 } else {
+                    let version = src.get_u8();
+                    if version != TX_VERSION {
+                        return Err(DecodeError::TransactionVersion(version));
+                    }
                     let timestamp = src.get_u64();
                     self.timestamp = Some(timestamp);
-                    self.decode_with_timestamp(src)
+                    self.decode_with_timestamp(src, limits)
                 }
             }
-            (true, false, _, _) => self.decode_with_timestamp(src),
-            (_, true, false, _) => self.decode_with_binary_len(src),
-            (_, _, true, false) => self.decode_with_binary(src),
-            (_, _, _, true) => self.decode_with_aux_len(src),
-        };
-        Ok(tx)
+            (true, false, _, _) => self.decode_with_timestamp(src, limits),
+            (_, true, false, _) => self.decode_with_binary_len(src, limits),
+            (_, _, true, false) => self.decode_with_binary(src, limits),
+            (_, _, _, true) => Ok(self.decode_with_aux_len(src)),
+        }
     }
 }
 
@@ -152,11 +267,15 @@ pub struct TransactionsState {
 }
 
 impl TransactionsState {
-    fn decode_inner(&mut self, src: &mut BytesMut) -> Result<Option<Transactions>, DecodeError> {
+    fn decode_inner(
+        &mut self,
+        src: &mut BytesMut,
+        limits: &Limits,
+    ) -> Result<Option<Transactions>, DecodeError> {
         let n_read = self.read.as_ref().unwrap().len(); // This is safe
         let n_remaining_txs = self.n_txs.unwrap() as usize - n_read;
         for _ in 0..n_remaining_txs {
-            match self.tx_state.decode(src)? {
+            match self.tx_state.decode(src, limits)? {
                 Some(transaction) => {
                     self.tx_state = TransactionState::default();
                     self.read.as_mut().unwrap().push(transaction)
@@ -169,30 +288,35 @@ impl TransactionsState {
         Ok(Some(transactions))
     }
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Transactions>, DecodeError> {
+    fn decode(
+        &mut self,
+        src: &mut BytesMut,
+        limits: &Limits,
+    ) -> Result<Option<Transactions>, DecodeError> {
         if self.n_txs.is_none() {
             if src.len() < 4 {
                 return Ok(None);
             }
 
             let n_txs = src.get_u32();
+            check_limit("transactions", n_txs as usize, limits.max_txs)?;
             self.n_txs = Some(n_txs);
             self.read = Some(vec![]);
-            self.decode_inner(src)
+            self.decode_inner(src, limits)
         } else {
-            self.decode_inner(src)
+            self.decode_inner(src, limits)
         }
     }
 }
 
 #[derive(Debug, Default)]
 pub struct ReconcileState {
-    minisketch_len: Option<u32>,
+    capacity: Option<u32>,
 }
 
 impl ReconcileState {
     fn decode_inner(&mut self, src: &mut BytesMut) -> Option<Minisketch> {
-        let total_len = self.minisketch_len.unwrap() as usize * 32;
+        let total_len = self.capacity.unwrap() as usize * 8;
         if src.remaining() < total_len {
             None
         } else {
@@ -201,13 +325,18 @@ impl ReconcileState {
         }
     }
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Minisketch>, DecodeError> {
-        if self.minisketch_len.is_none() {
+    fn decode(
+        &mut self,
+        src: &mut BytesMut,
+        limits: &Limits,
+    ) -> Result<Option<Minisketch>, DecodeError> {
+        if self.capacity.is_none() {
             if src.remaining() < 4 {
                 Ok(None)
             } else {
-                let minisketch_len = src.get_u32();
-                self.minisketch_len = Some(minisketch_len);
+                let capacity = src.get_u32();
+                check_limit("minisketch", capacity as usize, limits.max_capacity)?;
+                self.capacity = Some(capacity);
                 Ok(self.decode_inner(src))
             }
         } else {
@@ -227,14 +356,22 @@ impl TransactionInvState {
             None
         } else {
             let tx_ids = (0..n_tx_ids)
-                .map(|_| src.split_to(DIGEST_LEN).freeze())
+                .map(|_| {
+                    let mut tx_id = [0; TX_ID_LEN];
+                    src.copy_to_slice(&mut tx_id);
+                    TxId(tx_id)
+                })
                 .collect();
             let tx_inv = TransactionInv { tx_ids };
             Some(tx_inv)
         }
     }
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<TransactionInv>, DecodeError> {
+    fn decode(
+        &mut self,
+        src: &mut BytesMut,
+        limits: &Limits,
+    ) -> Result<Option<TransactionInv>, DecodeError> {
         if let Some(n_tx_ids) = self.n_tx_ids {
             Ok(Self::decode_inner(n_tx_ids, src))
         } else {
@@ -242,6 +379,7 @@ impl TransactionInvState {
                 Ok(None)
             } else {
                 let n_tx_ids = src.get_u32();
+                check_limit("tx_ids", n_tx_ids as usize, limits.max_tx_ids)?;
                 self.n_tx_ids = Some(n_tx_ids);
                 Ok(Self::decode_inner(n_tx_ids, src))
             }
@@ -249,16 +387,76 @@ impl TransactionInvState {
     }
 }
 
+#[derive(Debug, Default)]
+pub struct ReconcileResponseState {
+    tx_inv_state: TransactionInvState,
+    tx_ids: Option<Vec<TxId>>,
+    n_missing: Option<u32>,
+}
+
+impl ReconcileResponseState {
+    fn decode_missing(n_missing: u32, src: &mut BytesMut) -> Option<Vec<ShortId>> {
+        if src.remaining() < n_missing as usize * 8 {
+            None
+        } else {
+            let missing = (0..n_missing).map(|_| ShortId(src.get_u64())).collect();
+            Some(missing)
+        }
+    }
+
+    fn decode_with_tx_ids(
+        &mut self,
+        src: &mut BytesMut,
+        limits: &Limits,
+    ) -> Result<Option<ReconcileResponse>, DecodeError> {
+        let n_missing = match self.n_missing {
+            Some(some) => some,
+            None => {
+                if src.remaining() < 4 {
+                    return Ok(None);
+                }
+                let n_missing = src.get_u32();
+                check_limit("missing", n_missing as usize, limits.max_tx_ids)?;
+                self.n_missing = Some(n_missing);
+                n_missing
+            }
+        };
+
+        let response = Self::decode_missing(n_missing, src).map(|missing| ReconcileResponse {
+            tx_ids: self.tx_ids.take().unwrap(), // This is safe
+            missing,
+        });
+        Ok(response)
+    }
+
+    fn decode(
+        &mut self,
+        src: &mut BytesMut,
+        limits: &Limits,
+    ) -> Result<Option<ReconcileResponse>, DecodeError> {
+        if self.tx_ids.is_none() {
+            match self.tx_inv_state.decode(src, limits)? {
+                Some(inv) => self.tx_ids = Some(inv.tx_ids),
+                None => return Ok(None),
+            }
+        }
+        self.decode_with_tx_ids(src, limits)
+    }
+}
+
 #[derive(Debug)]
 pub enum DecodeState {
     Type,
+    Hello(HelloState),
+    HelloAck,
     Poll,
     Status(StatusState),
     Reconcile(ReconcileState),
-    ReconcileResponse(TransactionsState),
+    ReconcileResponse(ReconcileResponseState),
     TransactionInv(TransactionInvState),
     Transaction(TransactionState),
     Transactions(TransactionsState),
+    Inventory(TransactionInvState),
 }
 
 /*
@@ -268,6 +466,17 @@ Decoding error
 #[derive(Debug)]
 pub enum DecodeError {
     UnexpectedType,
+    /// The peer is on a different network.
+    MagicBytes,
+    InvalidAddress,
+    /// The transaction encoding version is unsupported.
+    TransactionVersion(u8),
+    /// A field or message exceeded its limit.
+    Oversized {
+        field: &'static str,
+        len: usize,
+        limit: usize,
+    },
     IO(io::Error),
 }
 
@@ -291,24 +500,20 @@ impl MessageCodec {
             0 => DecodeState::Poll,
             1 => DecodeState::Status(StatusState::default()),
             2 => DecodeState::Reconcile(ReconcileState::default()),
-            3 => DecodeState::ReconcileResponse(TransactionsState::default()),
+            3 => DecodeState::ReconcileResponse(ReconcileResponseState::default()),
             4 => DecodeState::Transaction(TransactionState::default()),
             5 => DecodeState::TransactionInv(TransactionInvState::default()),
             6 => DecodeState::Transactions(TransactionsState::default()),
+            7 => DecodeState::Inventory(TransactionInvState::default()),
+            8 => DecodeState::Hello(HelloState::default()),
+            9 => DecodeState::HelloAck,
             _ => return Err(DecodeError::UnexpectedType),
         };
 
         Ok(Some(()))
     }
-}
-
-impl Decoder for MessageCodec {
-    type Item = Message;
-    type Error = DecodeError;
-
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, DecodeError> {
-        trace!("received raw message; {:?}", src);
 
+    fn decode_message(&mut self, src: &mut BytesMut) -> Result<Option<Message>, DecodeError> {
         if let DecodeState::Type = self.state {
             if self.decode_type(src)?.is_none() {
                 return Ok(None);
@@ -316,48 +521,92 @@ impl Decoder for MessageCodec {
         }
 
         trace!("decoding message; {:?}", self.state);
+        let limits = self.limits;
         match &mut self.state {
+            DecodeState::Hello(inner_state) => inner_state.decode(src, &limits).map(|opt| {
+                opt.map(|hello| {
+                    self.state = DecodeState::Type;
+                    Message::Hello(hello)
+                })
+            }),
+            DecodeState::HelloAck => {
+                if src.remaining() < SIGNATURE_LEN {
+                    return Ok(None);
+                }
+                self.state = DecodeState::Type;
+                let signature = src.split_to(SIGNATURE_LEN).freeze();
+                Ok(Some(Message::HelloAck(signature)))
+            }
             DecodeState::Poll => {
                 self.state = DecodeState::Type;
                 Ok(Some(Message::Poll))
             }
-            DecodeState::Status(inner_state) => inner_state.decode(src).map(|opt| {
+            DecodeState::Status(inner_state) => inner_state.decode(src, &limits).map(|opt| {
                 opt.map(|status| {
                     self.state = DecodeState::Type;
                     Message::Status(status)
                 })
             }),
-            DecodeState::Reconcile(inner_state) => inner_state.decode(src).map(|opt| {
+            DecodeState::Reconcile(inner_state) => inner_state.decode(src, &limits).map(|opt| {
                 opt.map(|minisketch| {
                     self.state = DecodeState::Type;
                     Message::Reconcile(minisketch)
                 })
             }),
-            DecodeState::ReconcileResponse(inner_state) => inner_state.decode(src).map(|opt| {
-                opt.map(|txs| {
-                    self.state = DecodeState::Type;
-                    Message::ReconcileResponse(txs)
+            DecodeState::ReconcileResponse(inner_state) => {
+                inner_state.decode(src, &limits).map(|opt| {
+                    opt.map(|response| {
+                        self.state = DecodeState::Type;
+                        Message::ReconcileResponse(response)
+                    })
                 })
-            }),
-            DecodeState::Transaction(inner_state) => inner_state.decode(src).map(|opt| {
+            }
+            DecodeState::Transaction(inner_state) => inner_state.decode(src, &limits).map(|opt| {
                 opt.map(|txs| {
                     self.state = DecodeState::Type;
                     Message::Transaction(txs)
                 })
             }),
-            DecodeState::TransactionInv(inner_state) => inner_state.decode(src).map(|opt| {
-                opt.map(|inv| {
-                    self.state = DecodeState::Type;
-                    Message::TransactionInv(inv)
+            DecodeState::TransactionInv(inner_state) => {
+                inner_state.decode(src, &limits).map(|opt| {
+                    opt.map(|inv| {
+                        self.state = DecodeState::Type;
+                        Message::TransactionInv(inv)
+                    })
                 })
-            }),
-            DecodeState::Transactions(inner_state) => inner_state.decode(src).map(|opt| {
+            }
+            DecodeState::Transactions(inner_state) => inner_state.decode(src, &limits).map(|opt| {
                 opt.map(|txs| {
                     self.state = DecodeState::Type;
                     Message::Transactions(txs)
                 })
             }),
+            DecodeState::Inventory(inner_state) => inner_state.decode(src, &limits).map(|opt| {
+                opt.map(|inv| {
+                    self.state = DecodeState::Type;
+                    Message::Inventory(inv)
+                })
+            }),
             _ => unreachable!(),
         }
     }
 }
+
+impl Decoder for MessageCodec {
+    type Item = Message;
+    type Error = DecodeError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, DecodeError> {
+        trace!("received raw message; {:?}", src);
+
+        // Track the length of the current message
+        let start_len = src.len();
+        let result = self.decode_message(src);
+        self.message_len += start_len - src.len();
+        check_limit("message", self.message_len, self.limits.max_message_len)?;
+        if let Ok(Some(_)) = result {
+            self.message_len = 0;
+        }
+        result
+    }
+}
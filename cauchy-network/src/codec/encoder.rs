@@ -1,4 +1,4 @@
-use std::io;
+use std::{io, net::IpAddr};
 
 use bytes::buf::BufMut;
 use bytes::BytesMut;
@@ -18,68 +18,85 @@ impl From<io::Error> for EncodingError {
     }
 }
 
-fn put_transaction(tx: Transaction, dst: &mut BytesMut) {
-    let binary_len = tx.binary.len();
-    let aux_len = tx.aux_data.len();
-    dst.reserve(8 + 4 + binary_len + 4 + aux_len);
-
-    dst.put_u64(tx.timestamp);
-    dst.put_u32(binary_len as u32);
-    dst.put(tx.binary);
-    dst.put_u32(aux_len as u32);
-    dst.put(tx.aux_data);
-}
-
 impl Encoder<Message> for MessageCodec {
     type Error = EncodingError;
 
     fn encode(&mut self, item: Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
         trace!("encoding {:?}", item);
         match item {
+            Message::Hello(hello) => {
+                let pubkey_len = hello.pubkey.len();
+                dst.reserve(1 + 4 + 4 + 8 + 2 + pubkey_len + 1 + 16 + 2 + 4 + 8);
+
+                dst.put_u8(8);
+                dst.put_slice(&MAGIC_BYTES);
+                dst.put_u32(hello.version);
+                dst.put_u64(hello.features);
+                dst.put_u16(pubkey_len as u16);
+                dst.put(hello.pubkey);
+                match hello.addr.ip() {
+                    IpAddr::V4(ip) => {
+                        dst.put_u8(4);
+                        dst.put_slice(&ip.octets());
+                    }
+                    IpAddr::V6(ip) => {
+                        dst.put_u8(6);
+                        dst.put_slice(&ip.octets());
+                    }
+                }
+                dst.put_u16(hello.addr.port());
+                dst.put_u32(hello.radius);
+                dst.put_u64(hello.nonce);
+            }
+            Message::HelloAck(signature) => {
+                dst.reserve(1 + SIGNATURE_LEN);
+                dst.put_u8(9);
+                dst.put(signature);
+            }
             Message::Poll => {
                 dst.reserve(1);
                 dst.put_u8(0)
             }
             Message::Status(status) => {
                 let oddsketch_len = status.oddsketch.len();
-                dst.reserve(1 + 4 + oddsketch_len + DIGEST_LEN + 8);
+                dst.reserve(1 + 4 + oddsketch_len + DIGEST_LEN + 8 + 8 + SIGNATURE_LEN);
 
                 dst.put_u8(1);
                 dst.put_u16(oddsketch_len as u16); // This is safe
                 dst.put(status.oddsketch);
                 dst.put(status.root);
                 dst.put_u64(status.nonce);
+                dst.put_u64(status.round);
+                dst.put(status.signature);
             }
             Message::Reconcile(minisketch) => {
-                let minisketch_raw = minisketch.0;
-                let minisketch_len = minisketch_raw.len() as u32;
-                dst.reserve(1);
+                let capacity = minisketch.capacity();
+                dst.reserve(1 + 4 + capacity * 8);
 
                 dst.put_u8(2);
-                dst.put_u32(minisketch_len / 32); // This is safe
-                dst.put(minisketch_raw);
+                dst.put_u32(capacity as u32);
+                dst.put(minisketch.0);
             }
-            Message::ReconcileResponse(txs) => {
-                dst.reserve(1 + 4);
+            Message::ReconcileResponse(response) => {
+                let n_tx_ids = response.tx_ids.len();
+                let n_missing = response.missing.len();
+                dst.reserve(1 + 4 + n_tx_ids * DIGEST_LEN + 4 + n_missing * 8);
 
                 dst.put_u8(3);
-                let n_txs = txs.txs.len();
-                dst.put_u32(n_txs as u32);
-                for tx in txs.txs {
-                    put_transaction(tx, dst);
+                dst.put_u32(n_tx_ids as u32);
+                for tx_id in response.tx_ids {
+                    dst.put_slice(&tx_id.0);
+                }
+                dst.put_u32(n_missing as u32);
+                for short_id in response.missing {
+                    dst.put_u64(short_id.0);
                 }
             }
             Message::Transaction(tx) => {
-                let binary_len = tx.binary.len();
-                let aux_len = tx.aux_data.len();
-                dst.reserve(1 + 8 + 4 + binary_len + 4 + aux_len);
+                dst.reserve(1 + tx.encoded_len());
 
                 dst.put_u8(4);
-                dst.put_u64(tx.timestamp);
-                dst.put_u32(binary_len as u32);
-                dst.put(tx.binary);
-                dst.put_u32(aux_len as u32);
-                dst.put(tx.aux_data);
+                tx.encode(dst);
             }
             Message::TransactionInv(tx_inv) => {
                 let n_tx_ids = tx_inv.tx_ids.len();
@@ -89,7 +106,18 @@ impl Encoder<Message> for MessageCodec {
                 dst.put_u8(5);
                 dst.put_u32(n_tx_ids as u32);
                 for tx_id in tx_inv.tx_ids {
-                    dst.put(tx_id);
+                    dst.put_slice(&tx_id.0);
+                }
+            }
+            Message::Inventory(tx_inv) => {
+                let n_tx_ids = tx_inv.tx_ids.len();
+                let tx_id_size = n_tx_ids * DIGEST_LEN;
+                dst.reserve(1 + 4 + tx_id_size);
+
+                dst.put_u8(7);
+                dst.put_u32(n_tx_ids as u32);
+                for tx_id in tx_inv.tx_ids {
+                    dst.put_slice(&tx_id.0);
                 }
             }
             Message::Transactions(txs) => {
@@ -99,7 +127,7 @@ impl Encoder<Message> for MessageCodec {
                 let n_txs = txs.txs.len();
                 dst.put_u32(n_txs as u32);
                 for tx in txs.txs {
-                    put_transaction(tx, dst);
+                    tx.encode(dst);
                 }
             }
         }
@@ -5,17 +5,30 @@ pub use decoder::*;
 pub use encoder::*;
 
 const DIGEST_LEN: usize = 32;
+const SIGNATURE_LEN: usize = 64;
 
 pub const MAGIC_BYTES: [u8; 4] = [1, 2, 3, 4];
 
+/// The protocol version sent in `Hello`.
+pub const PROTOCOL_VERSION: u32 = 4;
+
+/// The feature bits sent in `Hello`.
+pub const FEATURES: u64 = 0;
+
+use bytes::Bytes;
 use common::network::*;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Message {
+    Hello(Hello),
+    /// Acknowledges the peers `Hello`, with a signature of the handshake transcript.
+    HelloAck(Bytes),
     Poll,
     Status(Status),
     Reconcile(Minisketch),
-    ReconcileResponse(Transactions),
+    ReconcileResponse(ReconcileResponse),
+    /// Sent in response to `Reconcile` when the symmetric difference exceeds the sketch capacity.
+    Inventory(TransactionInv),
     Transaction(Transaction),
     TransactionInv(TransactionInv),
     Transactions(Transactions),
@@ -25,18 +38,80 @@ pub enum Message {
 Message codec
 */
 
+/// Limits enforced by the decoder, so that peers cannot force us to buffer unbounded data.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Maximum length of an entire message.
+    pub max_message_len: usize,
+    pub max_pubkey_len: usize,
+    pub max_oddsketch_len: usize,
+    /// Maximum minisketch capacity in `Reconcile`.
+    pub max_capacity: usize,
+    pub max_binary_len: usize,
+    pub max_aux_len: usize,
+    /// Maximum number of transactions in `Transactions`.
+    pub max_txs: usize,
+    /// Maximum number of IDs in `TransactionInv`, `Inventory` and `ReconcileResponse`.
+    pub max_tx_ids: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_message_len: 1 << 24,
+            max_pubkey_len: 64,
+            max_oddsketch_len: 1 << 13,
+            max_capacity: 1 << 12,
+            max_binary_len: 1 << 20,
+            max_aux_len: 1 << 16,
+            max_txs: 1 << 10,
+            max_tx_ids: 1 << 16,
+        }
+    }
+}
+
+impl Limits {
+    /// Maximum number of transactions to request in one `TransactionInv`, such that the `Transactions` response
+    /// fits within the limits however large each transaction is.
+    pub fn max_txs_per_request(&self) -> usize {
+        let max_tx_len = 1 + 8 + 4 + self.max_binary_len + 4 + self.max_aux_len;
+        let max_by_len = self.max_message_len.saturating_sub(1 + 4) / max_tx_len;
+        self.max_txs.min(self.max_tx_ids).min(max_by_len).max(1)
+    }
+
+    /// Whether the limits admit the oddsketches and minisketches of a player with the given radius.
+    ///
+    /// The oddsketch is `4 * radius` bytes with a `u16` length prefix, minisketches have capacity at most the radius.
+    pub fn supports_radius(&self, radius: usize) -> bool {
+        let max_oddsketch_len = self.max_oddsketch_len.min(std::u16::MAX as usize);
+        radius <= self.max_capacity && radius <= max_oddsketch_len / 4
+    }
+}
+
 pub struct MessageCodec {
     state: DecodeState,
+    limits: Limits,
+    /// Bytes consumed by the message currently being decoded.
+    message_len: usize,
 }
 
-impl Default for MessageCodec {
-    fn default() -> Self {
+impl MessageCodec {
+    /// Construct a `MessageCodec` enforcing the given limits.
+    pub fn new(limits: Limits) -> Self {
         Self {
             state: DecodeState::Type,
+            limits,
+            message_len: 0,
         }
     }
 }
 
+impl Default for MessageCodec {
+    fn default() -> Self {
+        Self::new(Limits::default())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use bytes::{Bytes, BytesMut};
@@ -50,10 +125,13 @@ mod tests {
         let root: Vec<u8> = (0..DIGEST_LEN).map(|_| rng.gen()).collect();
         let oddsketch_len = 128; // TODO: Randomize
         let oddsketch: Vec<u8> = (0..oddsketch_len).map(|_| rng.gen()).collect();
+        let signature: Vec<u8> = (0..SIGNATURE_LEN).map(|_| rng.gen()).collect();
         Status {
             oddsketch: Bytes::from(oddsketch),
             root: Bytes::from(root),
             nonce: 324,
+            round: 12,
+            signature: Bytes::from(signature),
         }
     }
 
@@ -76,6 +154,72 @@ mod tests {
         (0..DIGEST_LEN).map(|_| rng.gen()).collect()
     }
 
+    fn generate_random_tx_id() -> TxId {
+        TxId(rand::thread_rng().gen())
+    }
+
+    #[test]
+    fn hello_complete() {
+        let mut buf = BytesMut::default();
+        let mut codec = MessageCodec::default();
+
+        let hello = Hello {
+            version: PROTOCOL_VERSION,
+            features: FEATURES,
+            pubkey: Bytes::from(generate_random_digest()),
+            addr: "[::1]:1080".parse().unwrap(),
+            radius: 128,
+            nonce: 42,
+        };
+        let signature = Bytes::from(vec![7; SIGNATURE_LEN]);
+
+        codec
+            .encode(Message::Hello(hello.clone()), &mut buf)
+            .expect("encoding error");
+        codec
+            .encode(Message::HelloAck(signature.clone()), &mut buf)
+            .expect("encoding error");
+
+        let result = codec
+            .decode(&mut buf)
+            .expect("decoding error")
+            .expect("decoding incomplete");
+        assert_eq!(result, Message::Hello(hello));
+
+        let result = codec
+            .decode(&mut buf)
+            .expect("decoding error")
+            .expect("decoding incomplete");
+        assert_eq!(result, Message::HelloAck(signature));
+
+        let result = codec.decode(&mut buf).expect("decoding error");
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn hello_magic_bytes() {
+        let mut buf = BytesMut::default();
+        let mut codec = MessageCodec::default();
+
+        let hello = Hello {
+            version: PROTOCOL_VERSION,
+            features: FEATURES,
+            pubkey: Bytes::new(),
+            addr: "127.0.0.1:1080".parse().unwrap(),
+            radius: 128,
+            nonce: 0,
+        };
+        codec
+            .encode(Message::Hello(hello), &mut buf)
+            .expect("encoding error");
+        buf[1] ^= 0xff;
+
+        match codec.decode(&mut buf) {
+            Err(DecodeError::MagicBytes) => (),
+            other => panic!("unexpected result; {:?}", other),
+        }
+    }
+
     #[test]
     fn poll_complete() {
         let mut buf = BytesMut::default();
@@ -141,12 +285,37 @@ mod tests {
         let mut buf = BytesMut::default();
         let mut codec = MessageCodec::default();
 
-        let n_txs = 128; // TODO: Randomize
-        let txs: Vec<_> = (0..n_txs).map(|_| generate_random_tx()).collect();
-        let transactions = Transactions { txs };
+        let n_tx_ids = 128; // TODO: Randomize
+        let tx_ids: Vec<_> = (0..n_tx_ids).map(|_| generate_random_tx_id()).collect();
+        let missing: Vec<ShortId> = (0..64).map(|_| ShortId(rand::thread_rng().gen())).collect();
+        let response = ReconcileResponse { tx_ids, missing };
+
+        codec
+            .encode(Message::ReconcileResponse(response.clone()), &mut buf)
+            .expect("encoding error");
+
+        let result = codec
+            .decode(&mut buf)
+            .expect("decoding error")
+            .expect("decoding incomplete");
+
+        assert_eq!(result, Message::ReconcileResponse(response));
+
+        let result = codec.decode(&mut buf).expect("decoding error");
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn inventory_complete() {
+        let mut buf = BytesMut::default();
+        let mut codec = MessageCodec::default();
+
+        let n_tx_ids = 128; // TODO: Randomize
+        let tx_ids: Vec<_> = (0..n_tx_ids).map(|_| generate_random_tx_id()).collect();
+        let inv = TransactionInv { tx_ids };
 
         codec
-            .encode(Message::ReconcileResponse(transactions.clone()), &mut buf)
+            .encode(Message::Inventory(inv.clone()), &mut buf)
             .expect("encoding error");
 
         let result = codec
@@ -154,7 +323,7 @@ mod tests {
             .expect("decoding error")
             .expect("decoding incomplete");
 
-        assert_eq!(result, Message::ReconcileResponse(transactions));
+        assert_eq!(result, Message::Inventory(inv));
 
         let result = codec.decode(&mut buf).expect("decoding error");
         assert_eq!(result, None);
@@ -182,15 +351,155 @@ mod tests {
         assert_eq!(result, None);
     }
 
+    #[test]
+    fn transaction_canonical() {
+        let mut buf = BytesMut::default();
+        let mut codec = MessageCodec::default();
+
+        let tx = generate_random_tx();
+        codec
+            .encode(Message::Transaction(tx.clone()), &mut buf)
+            .expect("encoding error");
+
+        // The wire format is the type byte followed by the canonical encoding
+        let mut canonical = BytesMut::default();
+        tx.encode(&mut canonical);
+        assert_eq!(buf[0], 4);
+        assert_eq!(buf[1..], canonical[..]);
+        assert_eq!(Transaction::decode(canonical.freeze()), Ok(tx.clone()));
+
+        // Auxiliary data is committed to by the ID
+        let mut other = tx.clone();
+        other.aux_data = Bytes::from_static(b"other");
+        assert_ne!(other.get_id(), tx.get_id());
+
+        // Unknown versions are rejected
+        buf[1] = TX_VERSION + 1;
+        match codec.decode(&mut buf) {
+            Err(DecodeError::TransactionVersion(version)) => assert_eq!(version, TX_VERSION + 1),
+            other => panic!("unexpected result; {:?}", other),
+        }
+    }
+
+    #[test]
+    fn oversized_field() {
+        let mut buf = BytesMut::default();
+        let mut codec = MessageCodec::new(Limits {
+            max_binary_len: 512,
+            ..Default::default()
+        });
+
+        codec
+            .encode(Message::Transaction(generate_random_tx()), &mut buf)
+            .expect("encoding error");
+
+        match codec.decode(&mut buf) {
+            Err(DecodeError::Oversized { field, len, limit }) => {
+                assert_eq!((field, len, limit), ("binary", 1024, 512))
+            }
+            other => panic!("unexpected result; {:?}", other),
+        }
+    }
+
+    #[test]
+    fn oversized_message() {
+        let mut buf = BytesMut::default();
+        let mut codec = MessageCodec::new(Limits {
+            max_message_len: 4096,
+            ..Default::default()
+        });
+
+        let txs: Vec<_> = (0..8).map(|_| generate_random_tx()).collect();
+        codec
+            .encode(Message::Transactions(Transactions { txs }), &mut buf)
+            .expect("encoding error");
+
+        match codec.decode(&mut buf) {
+            Err(DecodeError::Oversized { field, .. }) => assert_eq!(field, "message"),
+            other => panic!("unexpected result; {:?}", other),
+        }
+    }
+
+    #[test]
+    fn supports_radius() {
+        let limits = Limits {
+            max_oddsketch_len: 1024,
+            max_capacity: 512,
+            ..Default::default()
+        };
+        assert!(limits.supports_radius(256));
+        assert!(!limits.supports_radius(257));
+
+        let limits = Limits {
+            max_capacity: 1024,
+            ..limits
+        };
+        assert!(!limits.supports_radius(512));
+
+        // Oddsketch lengths are bounded by their prefix
+        let limits = Limits {
+            max_oddsketch_len: 1 << 20,
+            max_capacity: 1 << 20,
+            ..limits
+        };
+        assert!(limits.supports_radius(std::u16::MAX as usize / 4));
+        assert!(!limits.supports_radius(std::u16::MAX as usize / 4 + 1));
+        assert!(!limits.supports_radius(std::usize::MAX));
+    }
+
+    #[test]
+    fn max_txs_per_request() {
+        let limits = Limits {
+            max_message_len: 8192,
+            max_binary_len: 1024,
+            max_aux_len: 64,
+            ..Default::default()
+        };
+        let max_txs = limits.max_txs_per_request();
+        assert_eq!(max_txs, 7);
+        let mut codec = MessageCodec::new(limits);
+
+        // A response to a full request of maximal transactions fits, one more does not
+        let tx = Transaction {
+            timestamp: 0,
+            binary: Bytes::from(vec![0; 1024]),
+            aux_data: Bytes::from(vec![0; 64]),
+        };
+        let mut buf = BytesMut::default();
+        let txs = vec![tx.clone(); max_txs];
+        codec
+            .encode(Message::Transactions(Transactions { txs }), &mut buf)
+            .expect("encoding error");
+        codec
+            .decode(&mut buf)
+            .expect("decoding error")
+            .expect("decoding incomplete");
+
+        let txs = vec![tx; max_txs + 1];
+        codec
+            .encode(Message::Transactions(Transactions { txs }), &mut buf)
+            .expect("encoding error");
+        match codec.decode(&mut buf) {
+            Err(DecodeError::Oversized { field, .. }) => assert_eq!(field, "message"),
+            other => panic!("unexpected result; {:?}", other),
+        }
+
+        // The count limit applies to small transactions
+        let limits = Limits {
+            max_binary_len: 0,
+            max_aux_len: 0,
+            ..Default::default()
+        };
+        assert_eq!(limits.max_txs_per_request(), limits.max_txs);
+    }
+
     #[test]
     fn transactions_inv_complete() {
         let mut buf = BytesMut::default();
         let mut codec = MessageCodec::default();
 
         let n_tx_ids = 128; // TODO: Randomize
-        let tx_ids: Vec<_> = (0..n_tx_ids)
-            .map(|_| Bytes::from(generate_random_digest()))
-            .collect();
+        let tx_ids: Vec<_> = (0..n_tx_ids).map(|_| generate_random_tx_id()).collect();
         let inv = TransactionInv { tx_ids };
 
         codec
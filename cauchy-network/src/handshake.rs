@@ -0,0 +1,210 @@
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+
+use crate::{
+    codec::{DecodeError, PROTOCOL_VERSION},
+    FramedStream, Message,
+};
+use common::{network::Hello, services::HandshakeError};
+use crypto::Keypair;
+
+/// Time allowed for a peer to complete the handshake.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn next_message(framed: &mut FramedStream) -> Result<Message, HandshakeError> {
+    match framed.next().await {
+        Some(Ok(message)) => Ok(message),
+        Some(Err(DecodeError::MagicBytes)) => Err(HandshakeError::MagicBytes),
+        Some(Err(DecodeError::IO(_))) | None => Err(HandshakeError::Closed),
+        Some(Err(_)) => Err(HandshakeError::UnexpectedMessage),
+    }
+}
+
+async fn handshake_inner(
+    framed: &mut FramedStream,
+    hello: Hello,
+    keypair: &Keypair,
+) -> Result<Hello, HandshakeError> {
+    framed
+        .send(Message::Hello(hello.clone()))
+        .await
+        .map_err(|_| HandshakeError::Closed)?;
+
+    let peer_hello = match next_message(framed).await? {
+        Message::Hello(peer_hello) => peer_hello,
+        _ => return Err(HandshakeError::UnexpectedMessage),
+    };
+    if peer_hello.version != PROTOCOL_VERSION {
+        return Err(HandshakeError::Version(peer_hello.version));
+    }
+
+    let signature = hello.sign_transcript(&peer_hello, keypair);
+    framed
+        .send(Message::HelloAck(signature))
+        .await
+        .map_err(|_| HandshakeError::Closed)?;
+    let peer_signature = match next_message(framed).await? {
+        Message::HelloAck(signature) => signature,
+        _ => return Err(HandshakeError::UnexpectedMessage),
+    };
+
+    // A peer presenting our own public key could reflect our signature back
+    if peer_hello.pubkey == hello.pubkey || !hello.verify_transcript(&peer_hello, &peer_signature) {
+        return Err(HandshakeError::Signature);
+    }
+
+    // Over Noise, the peer must be the node which signed its static key
+    if let Some(remote_identity) = framed.get_ref().remote_identity() {
+        if remote_identity != &peer_hello.pubkey[..] {
+            return Err(HandshakeError::Signature);
+        }
+    }
+    Ok(peer_hello)
+}
+
+/// Perform the handshake, returning the peers `Hello`.
+///
+/// Both sides send a `Hello`, then acknowledge the others with a signature of the transcript, so the handshake is
+/// symmetric. The peers `Hello` is only returned once its signature is verified against the public key it claims.
+pub async fn handshake(
+    framed: &mut FramedStream,
+    hello: Hello,
+    keypair: &Keypair,
+) -> Result<Hello, HandshakeError> {
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake_inner(framed, hello, keypair))
+        .await
+        .map_err(|_| HandshakeError::Timeout)?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use rand::rngs::OsRng;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_util::codec::Framed;
+
+    use super::*;
+    use crate::{
+        codec::{MessageCodec, FEATURES},
+        NoiseStream, PeerStream,
+    };
+    use common::services::Role;
+
+    fn hello(version: u32, keypair: &Keypair) -> Hello {
+        Hello {
+            version,
+            features: FEATURES,
+            pubkey: Bytes::copy_from_slice(keypair.public.as_bytes()),
+            addr: "127.0.0.1:1080".parse().unwrap(),
+            radius: 128,
+            nonce: rand::random(),
+        }
+    }
+
+    async fn connect(
+        (hello_a, keypair_a): (Hello, Arc<Keypair>),
+        (hello_b, keypair_b): (Hello, Arc<Keypair>),
+    ) -> (Result<Hello, HandshakeError>, Result<Hello, HandshakeError>) {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let side_a = async move {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let mut framed = Framed::new(PeerStream::Plain(tcp_stream), MessageCodec::default());
+            handshake(&mut framed, hello_a, &keypair_a).await
+        };
+        let side_b = async move {
+            let tcp_stream = TcpStream::connect(addr).await.unwrap();
+            let mut framed = Framed::new(PeerStream::Plain(tcp_stream), MessageCodec::default());
+            handshake(&mut framed, hello_b, &keypair_b).await
+        };
+        futures::join!(side_a, side_b)
+    }
+
+    fn side(version: u32) -> (Hello, Arc<Keypair>) {
+        let keypair = Keypair::generate(&mut OsRng);
+        (hello(version, &keypair), Arc::new(keypair))
+    }
+
+    #[tokio::test]
+    async fn compatible() {
+        let (side_a, side_b) = (side(PROTOCOL_VERSION), side(PROTOCOL_VERSION));
+        let (hello_a, hello_b) = (side_a.0.clone(), side_b.0.clone());
+        let (result_a, result_b) = connect(side_a, side_b).await;
+        assert_eq!(result_a.unwrap(), hello_b);
+        assert_eq!(result_b.unwrap(), hello_a);
+    }
+
+    #[tokio::test]
+    async fn incompatible_version() {
+        let (result_a, _) = connect(side(PROTOCOL_VERSION), side(PROTOCOL_VERSION + 1)).await;
+        match result_a {
+            Err(HandshakeError::Version(version)) => assert_eq!(version, PROTOCOL_VERSION + 1),
+            other => panic!("unexpected result; {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn impersonation() {
+        // Side B claims the public key of another node, without holding its secret key
+        let (hello_victim, _) = side(PROTOCOL_VERSION);
+        let (mut hello_b, keypair_b) = side(PROTOCOL_VERSION);
+        hello_b.pubkey = hello_victim.pubkey;
+        let (result_a, _) = connect(side(PROTOCOL_VERSION), (hello_b, keypair_b)).await;
+        match result_a {
+            Err(HandshakeError::Signature) => (),
+            other => panic!("unexpected result; {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn noise_identity() {
+        let static_key = || {
+            let params = crate::NOISE_PARAMS.parse().unwrap();
+            snow::Builder::new(params)
+                .generate_keypair()
+                .unwrap()
+                .private
+        };
+        let (key_a, key_b) = (static_key(), static_key());
+        let (hello_a, keypair_a) = side(PROTOCOL_VERSION);
+        let (hello_b, keypair_b) = side(PROTOCOL_VERSION);
+
+        // Side B secures the connection as one node, then presents itself as another
+        let noise_keypair_b = Keypair::generate(&mut OsRng);
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let side_a = async move {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let stream = NoiseStream::handshake(tcp_stream, Role::Responder, &key_a, &keypair_a)
+                .await
+                .unwrap();
+            let peer_stream = PeerStream::Noise(Box::new(stream));
+            let mut framed = Framed::new(peer_stream, MessageCodec::default());
+            handshake(&mut framed, hello_a, &keypair_a).await
+        };
+        let side_b = async move {
+            let tcp_stream = TcpStream::connect(addr).await.unwrap();
+            let stream =
+                NoiseStream::handshake(tcp_stream, Role::Initiator, &key_b, &noise_keypair_b)
+                    .await
+                    .unwrap();
+            let peer_stream = PeerStream::Noise(Box::new(stream));
+            let mut framed = Framed::new(peer_stream, MessageCodec::default());
+            handshake(&mut framed, hello_b, &keypair_b).await
+        };
+        let (result_a, _) = futures::join!(side_a, side_b);
+        assert!(matches!(result_a, Err(HandshakeError::Signature)));
+    }
+
+    #[tokio::test]
+    async fn own_pubkey() {
+        let (hello_a, keypair) = side(PROTOCOL_VERSION);
+        let hello_b = hello(PROTOCOL_VERSION, &keypair);
+        let (result_a, result_b) = connect((hello_a, keypair.clone()), (hello_b, keypair)).await;
+        assert!(matches!(result_a, Err(HandshakeError::Signature)));
+        assert!(matches!(result_b, Err(HandshakeError::Signature)));
+    }
+}
@@ -1,13 +1,16 @@
 pub mod codec;
+mod handshake;
+mod noise;
 
-use tokio::net::TcpStream;
 use tokio_util::codec::Framed;
 
 pub use codec::Message;
+pub use handshake::*;
+pub use noise::*;
 
 /// Get crate version.
 pub fn get_version() -> String {
     env!("CARGO_PKG_VERSION").to_string()
 }
 
-pub type FramedStream = Framed<TcpStream, codec::MessageCodec>;
+pub type FramedStream = Framed<PeerStream, codec::MessageCodec>;
@@ -0,0 +1,481 @@
+use std::{
+    fs, io,
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::{Buf, BufMut, BytesMut};
+use crypto::{Keypair, PUBLIC_KEY_LEN};
+use snow::{
+    params::NoiseParams,
+    resolvers::{CryptoResolver, DefaultResolver},
+    Builder, TransportState,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::HANDSHAKE_TIMEOUT;
+use common::services::{HandshakeError, Role};
+
+/// Noise pattern used to secure peer connections.
+pub const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+/// Maximum length of a Noise message.
+const MAX_MESSAGE_LEN: usize = 65535;
+
+/// Length of the authentication tag appended to each encrypted message.
+const TAG_LEN: usize = 16;
+
+const MAX_PLAINTEXT_LEN: usize = MAX_MESSAGE_LEN - TAG_LEN;
+
+const READ_CHUNK_LEN: usize = 8192;
+
+const STATIC_KEY_DOMAIN: &[u8] = b"cauchy-noise-static";
+
+/// Load the static private key at `path`, generating and saving a new one if missing.
+pub fn load_static_key<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+    match fs::read(&path) {
+        Ok(private_key) => Ok(private_key),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            let params = NOISE_PARAMS.parse().unwrap(); // This is safe
+            let keypair = Builder::new(params)
+                .generate_keypair()
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+            crypto::write_secret(&path, &keypair.private)?;
+            Ok(keypair.private)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// The public key of a static private key.
+fn static_public(static_key: &[u8]) -> Result<Vec<u8>, HandshakeError> {
+    let params: NoiseParams = NOISE_PARAMS.parse().unwrap(); // This is safe
+    let mut dh = DefaultResolver
+        .resolve_dh(&params.dh)
+        .ok_or(HandshakeError::Noise)?;
+    if static_key.len() != dh.priv_len() {
+        return Err(HandshakeError::Noise);
+    }
+    dh.set(static_key);
+    Ok(dh.pubkey().to_vec())
+}
+
+/// The handshake payload binding a static key to a node key.
+///
+/// Layout: `pubkey || signature`, where the signature covers the static public key.
+fn identity_payload(keypair: &Keypair, static_public: &[u8]) -> Vec<u8> {
+    let signature = crypto::sign(keypair, &[STATIC_KEY_DOMAIN, static_public].concat());
+    [&keypair.public.as_bytes()[..], &signature[..]].concat()
+}
+
+/// Verify the identity payload of the peer against its authenticated static key, returning its node public key.
+fn verify_identity(payload: &[u8], remote_static: &[u8]) -> Result<Vec<u8>, HandshakeError> {
+    if payload.len() < PUBLIC_KEY_LEN {
+        return Err(HandshakeError::Signature);
+    }
+    let (pubkey, signature) = payload.split_at(PUBLIC_KEY_LEN);
+    if !crypto::verify(
+        pubkey,
+        &[STATIC_KEY_DOMAIN, remote_static].concat(),
+        signature,
+    ) {
+        return Err(HandshakeError::Signature);
+    }
+    Ok(pubkey.to_vec())
+}
+
+/// A stream encrypted and authenticated using the Noise protocol.
+///
+/// The static keys are bound to the node keys of each side, which sign their static key during the handshake.
+/// Each Noise message is prefixed by its length as a `u16`.
+pub struct NoiseStream<S> {
+    inner: S,
+    transport: TransportState,
+    remote_static: Vec<u8>,
+    /// The node public key which signed the remote static key.
+    remote_identity: Vec<u8>,
+    /// Encrypted bytes read from the inner stream.
+    read_buf: BytesMut,
+    /// Decrypted bytes not yet read.
+    plaintext: BytesMut,
+    /// Encrypted bytes not yet written to the inner stream.
+    write_buf: BytesMut,
+}
+
+async fn send_message<S: AsyncWrite + Unpin>(stream: &mut S, message: &[u8]) -> io::Result<()> {
+    stream.write_u16(message.len() as u16).await?;
+    stream.write_all(message).await
+}
+
+async fn recv_message<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Vec<u8>> {
+    let len = stream.read_u16().await?;
+    let mut message = vec![0; len as usize];
+    stream.read_exact(&mut message).await?;
+    Ok(message)
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> NoiseStream<S> {
+    async fn handshake_inner(
+        mut inner: S,
+        role: Role,
+        static_key: &[u8],
+        keypair: &Keypair,
+    ) -> Result<Self, HandshakeError> {
+        let params = NOISE_PARAMS.parse().unwrap(); // This is safe
+        let builder = Builder::new(params).local_private_key(static_key);
+        let mut state = match role {
+            Role::Initiator => builder.build_initiator(),
+            Role::Responder => builder.build_responder(),
+        }
+        .map_err(|_| HandshakeError::Noise)?;
+        let payload = identity_payload(keypair, &static_public(static_key)?);
+
+        // XX pattern: -> e, <- e ee s es, -> s se
+        // Each side sends its identity alongside its static key, which is its final message
+        let mut buf = vec![0; MAX_MESSAGE_LEN];
+        let mut remote_identity = None;
+        let mut n_written = 0;
+        while !state.is_handshake_finished() {
+            if state.is_my_turn() {
+                let carries_static = match role {
+                    Role::Initiator => n_written == 1,
+                    Role::Responder => n_written == 0,
+                };
+                let message_payload = if carries_static { &payload[..] } else { &[] };
+                let len = state
+                    .write_message(message_payload, &mut buf)
+                    .map_err(|_| HandshakeError::Noise)?;
+                send_message(&mut inner, &buf[..len])
+                    .await
+                    .map_err(|_| HandshakeError::Closed)?;
+                n_written += 1;
+            } else {
+                let message = recv_message(&mut inner)
+                    .await
+                    .map_err(|_| HandshakeError::Closed)?;
+                let len = state
+                    .read_message(&message, &mut buf)
+                    .map_err(|_| HandshakeError::Noise)?;
+                if let (None, Some(remote_static)) = (&remote_identity, state.get_remote_static()) {
+                    remote_identity = Some(verify_identity(&buf[..len], remote_static)?);
+                }
+            }
+        }
+
+        let remote_static = state
+            .get_remote_static()
+            .ok_or(HandshakeError::Noise)?
+            .to_vec();
+        let remote_identity = remote_identity.ok_or(HandshakeError::Noise)?;
+        let transport = state
+            .into_transport_mode()
+            .map_err(|_| HandshakeError::Noise)?;
+        Ok(Self {
+            inner,
+            transport,
+            remote_static,
+            remote_identity,
+            read_buf: BytesMut::new(),
+            plaintext: BytesMut::new(),
+            write_buf: BytesMut::new(),
+        })
+    }
+
+    /// Perform the Noise handshake over `inner` using our static private key, signed by the node keypair.
+    pub async fn handshake(
+        inner: S,
+        role: Role,
+        static_key: &[u8],
+        keypair: &Keypair,
+    ) -> Result<Self, HandshakeError> {
+        tokio::time::timeout(
+            HANDSHAKE_TIMEOUT,
+            Self::handshake_inner(inner, role, static_key, keypair),
+        )
+        .await
+        .map_err(|_| HandshakeError::Timeout)?
+    }
+}
+
+impl<S> NoiseStream<S> {
+    /// The remote static key, authenticated during the handshake.
+    pub fn remote_static(&self) -> &[u8] {
+        &self.remote_static
+    }
+
+    /// The node public key bound to the remote static key.
+    pub fn remote_identity(&self) -> &[u8] {
+        &self.remote_identity
+    }
+}
+
+impl<S: AsyncWrite + Unpin> NoiseStream<S> {
+    /// Write buffered encrypted bytes to the inner stream.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.write_buf.is_empty() {
+            match Pin::new(&mut self.inner).poll_write(cx, &self.write_buf) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(n)) => self.write_buf.advance(n),
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn invalid_data<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for NoiseStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            // Return pending plaintext
+            if !this.plaintext.is_empty() {
+                let n = buf.len().min(this.plaintext.len());
+                buf[..n].copy_from_slice(&this.plaintext[..n]);
+                this.plaintext.advance(n);
+                return Poll::Ready(Ok(n));
+            }
+
+            // Decrypt a complete message
+            if this.read_buf.len() >= 2 {
+                let len = u16::from_be_bytes([this.read_buf[0], this.read_buf[1]]) as usize;
+                if this.read_buf.len() >= 2 + len {
+                    this.read_buf.advance(2);
+                    let message = this.read_buf.split_to(len);
+                    let mut payload = vec![0; len];
+                    let n = this
+                        .transport
+                        .read_message(&message, &mut payload)
+                        .map_err(invalid_data)?;
+                    this.plaintext.extend_from_slice(&payload[..n]);
+                    continue;
+                }
+            }
+
+            // Read more from the inner stream
+            let mut chunk = [0; READ_CHUNK_LEN];
+            match Pin::new(&mut this.inner).poll_read(cx, &mut chunk) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Ok(0)),
+                Poll::Ready(Ok(n)) => this.read_buf.extend_from_slice(&chunk[..n]),
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for NoiseStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        // Only accept more once the previous message has been written
+        match this.poll_drain(cx) {
+            Poll::Ready(Ok(())) => (),
+            other => return other.map(|res| res.map(|_| 0)),
+        }
+
+        let n = buf.len().min(MAX_PLAINTEXT_LEN);
+        let mut message = vec![0; n + TAG_LEN];
+        let len = this
+            .transport
+            .write_message(&buf[..n], &mut message)
+            .map_err(invalid_data)?;
+        this.write_buf.reserve(2 + len);
+        this.write_buf.put_u16(len as u16);
+        this.write_buf.put_slice(&message[..len]);
+
+        // Begin writing, the remainder is written on flush
+        if let Poll::Ready(Err(err)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(err));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_drain(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_flush(cx),
+            other => other,
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_drain(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_shutdown(cx),
+            other => other,
+        }
+    }
+}
+
+/// A peer connection, optionally secured using Noise.
+pub enum PeerStream {
+    Plain(TcpStream),
+    Noise(Box<NoiseStream<TcpStream>>),
+}
+
+impl PeerStream {
+    /// The remote static key, if the connection is secured using Noise.
+    pub fn remote_static(&self) -> Option<&[u8]> {
+        match self {
+            Self::Plain(_) => None,
+            Self::Noise(stream) => Some(stream.remote_static()),
+        }
+    }
+
+    /// The node public key bound to the remote static key, if the connection is secured using Noise.
+    pub fn remote_identity(&self) -> Option<&[u8]> {
+        match self {
+            Self::Plain(_) => None,
+            Self::Noise(stream) => Some(stream.remote_identity()),
+        }
+    }
+}
+
+impl AsyncRead for PeerStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Noise(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for PeerStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Noise(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Noise(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Noise(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::OsRng;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn generate_key() -> snow::Keypair {
+        let params = NOISE_PARAMS.parse().unwrap();
+        Builder::new(params).generate_keypair().unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn static_key_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keys").join("noise");
+        let static_key = load_static_key(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // Reloading yields the same key
+        assert_eq!(load_static_key(&path).unwrap(), static_key);
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let (key_a, key_b) = (generate_key(), generate_key());
+        let (identity_a, identity_b) =
+            (Keypair::generate(&mut OsRng), Keypair::generate(&mut OsRng));
+        let (public_a, public_b) = (key_a.public.clone(), key_b.public.clone());
+        let (pubkey_a, pubkey_b) = (identity_a.public.to_bytes(), identity_b.public.to_bytes());
+        assert_eq!(static_public(&key_a.private).unwrap(), public_a);
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let payload: Vec<u8> = (0..3 * MAX_PLAINTEXT_LEN).map(|i| i as u8).collect();
+        let payload_inner = payload.clone();
+        let side_a = async move {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let mut stream =
+                NoiseStream::handshake(tcp_stream, Role::Responder, &key_a.private, &identity_a)
+                    .await
+                    .unwrap();
+            stream.write_all(&payload_inner).await.unwrap();
+            stream.flush().await.unwrap();
+            stream
+        };
+        let side_b = async move {
+            let tcp_stream = TcpStream::connect(addr).await.unwrap();
+            let mut stream =
+                NoiseStream::handshake(tcp_stream, Role::Initiator, &key_b.private, &identity_b)
+                    .await
+                    .unwrap();
+            let mut received = vec![0; payload.len()];
+            stream.read_exact(&mut received).await.unwrap();
+            assert_eq!(received, payload);
+            stream
+        };
+        let (stream_a, stream_b) = futures::join!(side_a, side_b);
+
+        // Each side authenticated the others static key, and the node key bound to it
+        assert_eq!(stream_a.remote_static(), &public_b[..]);
+        assert_eq!(stream_b.remote_static(), &public_a[..]);
+        assert_eq!(stream_a.remote_identity(), &pubkey_b[..]);
+        assert_eq!(stream_b.remote_identity(), &pubkey_a[..]);
+    }
+
+    #[test]
+    fn identity_binding() {
+        let identity = Keypair::generate(&mut OsRng);
+        let (key, other_key) = (generate_key(), generate_key());
+        let payload = identity_payload(&identity, &key.public);
+        assert_eq!(
+            verify_identity(&payload, &key.public).unwrap(),
+            identity.public.to_bytes().to_vec()
+        );
+
+        // The signature does not cover any other static key
+        assert!(matches!(
+            verify_identity(&payload, &other_key.public),
+            Err(HandshakeError::Signature)
+        ));
+        assert!(matches!(
+            verify_identity(&payload[..PUBLIC_KEY_LEN - 1], &key.public),
+            Err(HandshakeError::Signature)
+        ));
+    }
+}
@@ -1,7 +1,9 @@
+mod mempool;
 pub mod peer;
+mod validator;
 
 use std::{
-    convert::TryInto,
+    collections::HashSet,
     net::SocketAddr,
     sync::{
         atomic::{AtomicU64, Ordering},
@@ -11,72 +13,174 @@ use std::{
 };
 
 use bytes::Bytes;
-use crypto::blake3;
-use dashmap::DashMap;
 use futures_channel::mpsc;
 use futures_core::task::{Context, Poll};
 use futures_util::{future::abortable, stream::StreamExt};
-use network::{codec::*, FramedStream};
+use network::{codec::*, FramedStream, NoiseStream, PeerStream};
 use tokio::{net::TcpListener, sync::RwLock};
 use tokio_tower::pipeline::{Client, Server};
 use tokio_util::codec::Framed;
 use tower_buffer::Buffer;
 use tower_service::Service;
 use tower_util::ServiceExt;
-use tracing::{info, trace};
+use tracing::{info, trace, warn};
 
 use common::{network::*, services::*, FutResponse};
-use consensus::Entry;
-use crypto::{Minisketch as MinisketchCrypto, MinisketchError, Oddsketch};
-use database::{Database, Error as DatabaseError};
-use miner::MiningCoordinator;
+use consensus::{Entry, Round, RoundCertificate, SignedStatus};
+use crypto::{Keypair, MinisketchError};
+use database::{
+    Checkpoint, Database, DeleteTransaction, Error as DatabaseError, GetCertificate, GetCheckpoint,
+    GetTransaction, PutCertificate, PutCheckpoint, PutExecution, StorageBackend,
+    TransactionsByTime, MAX_DEFERRED,
+};
+pub use mempool::*;
+use miner::{MiningCoordinator, RawSite};
 use peer::{PeerClient, PeerServer};
+pub use validator::*;
 
 pub type SplitStream = futures_util::stream::SplitStream<FramedStream>;
 
-const DIGEST_LEN: usize = 32;
+/// The minimum minisketch capacity used during reconciliation.
+const MIN_CAPACITY: usize = 8;
+
+/// Choose a minisketch capacity from the oddsketches of two players.
+///
+/// The estimated difference is padded by half to absorb estimation error. The capacity is bounded by the radius of
+/// both players, as a peer rejects minisketches exceeding its own.
+fn choose_capacity(
+    oddsketch: &[u8],
+    peer_oddsketch: &[u8],
+    radius: usize,
+    peer_radius: usize,
+) -> usize {
+    let max_capacity = radius.min(peer_radius);
+    match crypto::estimate_difference(oddsketch, peer_oddsketch) {
+        Some(estimate) => (estimate + estimate / 2)
+            .max(MIN_CAPACITY)
+            .min(max_capacity),
+        None => max_capacity,
+    }
+}
+
+/// Delete transactions which have been dropped from the mempool.
+async fn delete_transactions<S: StorageBackend>(
+    database: &Database<S>,
+    txs: &[Transaction],
+) -> Result<(), DatabaseError> {
+    for tx in txs {
+        database
+            .clone()
+            .oneshot(DeleteTransaction(tx.get_id()))
+            .await?;
+    }
+    Ok(())
+}
 
 #[derive(Clone)]
 pub struct StateSnapshot {
-    pub oddsketch: Bytes,
-    pub root: Bytes,
-    pub minisketch: Bytes,
+    pub mempool: Mempool,
+    /// Transactions the last winner lacked, resubmitted in the following round.
+    pub deferred: Vec<Transaction>,
+    pub site: RawSite,
     pub best_nonce: Arc<AtomicU64>,
 }
 
 impl StateSnapshot {
     fn to_parts(&self) -> (Minisketch, Status) {
         let status = Status {
-            oddsketch: Bytes::from(self.oddsketch.clone()),
-            root: self.root.clone(),
+            oddsketch: self.mempool.oddsketch().clone(),
+            root: self.mempool.root(),
             nonce: self.best_nonce.load(Ordering::SeqCst) as u64, // TODO: Fix
+            round: consensus::current_round(),
+            signature: Bytes::new(),
         };
 
-        let minisketch = self.minisketch.clone();
+        let minisketch = self.mempool.minisketch().clone();
         (Minisketch(minisketch), status)
     }
+
+    /// Begin a new mining session if the root, and therefore the site, has changed.
+    async fn refresh_site(&mut self, pubkey: &[u8], mining_coordinator: &mut MiningCoordinator) {
+        let site = miner::site(pubkey, &self.mempool.root());
+        if site != self.site {
+            trace!("new site; {:?}", site);
+            self.site = site;
+            self.best_nonce = mining_coordinator
+                .call(miner::NewSession(site))
+                .await
+                .unwrap(); // This is safe
+        }
+    }
+
+    /// Construct a checkpoint of the state.
+    ///
+    /// Deferred transactions beyond `MAX_DEFERRED` are omitted, on restart they are restored to the mempool and the
+    /// checkpoint is discarded.
+    fn to_checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            oddsketch: self.mempool.oddsketch().clone(),
+            minisketch: self.mempool.minisketch().clone(),
+            root: self.mempool.root(),
+            site: self.site,
+            nonce: self.best_nonce.load(Ordering::SeqCst),
+            deferred: self
+                .deferred
+                .iter()
+                .take(MAX_DEFERRED)
+                .map(Transaction::get_id)
+                .collect(),
+        }
+    }
+
+    /// Restore stored transactions, holding back those which were deferred when the checkpoint was taken.
+    fn restore(&mut self, txs: Vec<Transaction>, deferred: &[TxId]) {
+        let deferred: HashSet<&TxId> = deferred.iter().collect();
+        for tx in txs {
+            if deferred.contains(&tx.get_id()) {
+                self.deferred.push(tx);
+            } else {
+                self.mempool.insert(tx);
+            }
+        }
+    }
+
+    /// Remove deferred transactions which have expired by `now`, in milliseconds since the Unix epoch.
+    fn expire_deferred(&mut self, now: u64) -> Vec<Transaction> {
+        let expiry = self.mempool.limits().expiry;
+        let (expired, deferred) = std::mem::take(&mut self.deferred)
+            .into_iter()
+            .partition(|tx| tx.timestamp.saturating_add(expiry) < now);
+        self.deferred = deferred;
+        expired
+    }
 }
 
 /// Player service
 #[derive(Clone)]
-pub struct Player<A, V> {
+pub struct Player<A, V, S> {
     arena: A,
     metadata: Arc<Metadata>,
     mining_coordinator: MiningCoordinator,
     state_snapshot: Arc<RwLock<StateSnapshot>>,
-    database: Database,
-    txs: Arc<DashMap<[u8; blake3::OUT_LEN], Transaction>>,
+    database: Database<S>,
     radius: usize,
-    vm_factory: V,
+    /// Limits enforced on messages from peers, these admit our own radius.
+    codec_limits: Limits,
+    validator: TxValidator<V>,
+    /// The node keypair, used to sign our `Status`.
+    keypair: Arc<Keypair>,
+    /// The Noise static private key, peer connections are encrypted if present.
+    noise_key: Option<Bytes>,
 }
 
 const PEER_BUFFER: usize = 128;
 
 /// Add new peer.
-impl<A, V> Service<NewPeer> for Player<A, V>
+impl<A, V, S> Service<NewPeer> for Player<A, V, S>
 where
     A: Clone + Send + Sync + 'static,
     V: Clone + Send + Sync + 'static,
+    S: StorageBackend,
     A: Service<(SocketAddr, PeerClient), Response = (), Error = InsertPeerError>,
     <A as Service<(SocketAddr, PeerClient)>>::Future: Send,
 {
@@ -88,50 +192,91 @@ where
         self.arena.poll_ready(cx).map_err(NewPeerError::Arena)
     }
 
-    fn call(&mut self, NewPeer(tcp_stream): NewPeer) -> Self::Future {
+    fn call(&mut self, NewPeer(tcp_stream, role): NewPeer) -> Self::Future {
         let addr = match tcp_stream.peer_addr() {
             Ok(ok) => ok,
             Err(err) => return Box::pin(async move { Err(NewPeerError::Network(err)) }),
         };
 
-        // Frame the TCP stream
-        let codec = MessageCodec::default();
-        let framed = Framed::new(tcp_stream, codec);
+        let hello = Hello {
+            version: PROTOCOL_VERSION,
+            features: FEATURES,
+            pubkey: self.metadata.pubkey.clone(),
+            addr: self.metadata.listen_addr,
+            radius: self.radius as u32,
+            nonce: rand::random(),
+        };
+        let player = self.clone();
+        let mut arena = self.arena.clone();
+        let fut = async move {
+            // Secure the TCP stream
+            let peer_stream = match &player.noise_key {
+                Some(noise_key) => {
+                    NoiseStream::handshake(tcp_stream, role, noise_key, &player.keypair)
+                        .await
+                        .map(|stream| PeerStream::Noise(Box::new(stream)))
+                        .map_err(NewPeerError::Handshake)?
+                }
+                None => PeerStream::Plain(tcp_stream),
+            };
+            let static_key = peer_stream.remote_static().map(Bytes::copy_from_slice);
 
-        // Construct request and response channels
-        let (response_sink, response_stream) = mpsc::channel(PEER_BUFFER);
-        let (request_sink, request_stream) = mpsc::channel(PEER_BUFFER);
+            // Frame the peer stream
+            let limits = player.codec_limits;
+            let codec = MessageCodec::new(limits);
+            let mut framed = Framed::new(peer_stream, codec);
 
-        // Server transport
-        let server_transport = peer::ServerTransport::new(framed, request_stream);
+            // Peers are only admitted after a successful handshake
+            let peer_hello = network::handshake(&mut framed, hello, &player.keypair)
+                .await
+                .map_err(NewPeerError::Handshake)?;
+            info!("completed handshake with {}", addr);
 
-        // Peer service
-        let service = PeerServer {
-            player: self.clone(),
-            perception: Default::default(),
-            response_sink,
-            radius: self.radius,
-        };
+            // The peers oddsketches and minisketches must fit within our limits
+            if !limits.supports_radius(peer_hello.radius as usize) {
+                return Err(NewPeerError::Handshake(HandshakeError::Radius(
+                    peer_hello.radius,
+                )));
+            }
 
-        // Construct abortable server
-        let server = Server::new(server_transport, service);
-        let (server_abortable, terminator) = abortable(server);
+            // Construct request and response channels
+            let (response_sink, response_stream) = mpsc::channel(PEER_BUFFER);
+            let (request_sink, request_stream) = mpsc::channel(PEER_BUFFER);
 
-        // Spawn server
-        tokio::spawn(server_abortable);
+            // Server transport
+            let server_transport = peer::ServerTransport::new(framed, request_stream);
 
-        // Construct client
-        let metadata = Arc::new(Metadata {
-            start_time: SystemTime::now(),
-            addr,
-        });
-        let client_transport = peer::ClientTransport::new(request_sink, response_stream);
-        let client_svc = Buffer::new(Client::new(client_transport), peer::BUFFER_SIZE);
-        let client = PeerClient::new(metadata, Default::default(), client_svc, terminator);
+            // Peer service
+            let radius = player.radius;
+            let service = PeerServer {
+                player,
+                perception: Default::default(),
+                response_sink,
+                radius,
+            };
 
-        // Add client to arena
-        let mut arena = self.arena.clone();
-        let fut = async move {
+            // Construct abortable server
+            let server = Server::new(server_transport, service);
+            let (server_abortable, terminator) = abortable(server);
+
+            // Spawn server
+            tokio::spawn(server_abortable);
+
+            // Construct client
+            let metadata = Arc::new(Metadata {
+                start_time: SystemTime::now(),
+                addr,
+                pubkey: peer_hello.pubkey,
+                listen_addr: peer_hello.addr,
+                radius: peer_hello.radius as usize,
+                static_key,
+            });
+            let client_transport = peer::ClientTransport::new(request_sink, response_stream);
+            let client_svc = Buffer::new(Client::new(client_transport), peer::BUFFER_SIZE);
+            let client =
+                PeerClient::new(metadata, Default::default(), client_svc, terminator, limits);
+
+            // Add client to arena
             arena
                 .call((addr, client))
                 .await
@@ -143,7 +288,7 @@ where
 }
 
 /// Remove peer.
-impl<A, V> Service<RemovePeer> for Player<A, V>
+impl<A, V, S> Service<RemovePeer> for Player<A, V, S>
 where
     A: Service<RemovePeer, Response = ()>,
     <A as Service<RemovePeer>>::Future: Send + 'static,
@@ -161,10 +306,11 @@ where
     }
 }
 
-impl<A, V> Player<A, V>
+impl<A, V, S> Player<A, V, S>
 where
     A: Clone + Default + Send + Sync + 'static,
-    V: Clone + Default + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+    S: StorageBackend,
     // Arena peer constructor interface
     A: Service<(SocketAddr, PeerClient), Response = (), Error = InsertPeerError>,
     <A as Service<(SocketAddr, PeerClient)>>::Future: Send,
@@ -173,52 +319,119 @@ where
     <A as Service<SampleQuery<PollStatus>>>::Response:
         std::fmt::Debug + IntoIterator<Item = (SocketAddr, Status)>,
     <A as Service<SampleQuery<PollStatus>>>::Error: std::fmt::Debug,
-    A: Service<DirectedQuery<Reconcile>>,
+    A: Service<DirectedQuery<Reconcile>, Response = Reconciliation>,
+    A: Service<DirectedQuery<GetMetadata>, Response = Arc<Metadata>>,
+    // Mempool interface
+    V: Service<Execute, Error = VMSpawnError>,
+    <V as Service<Execute>>::Response: Metered + Stateful + Send,
+    <V as Service<Execute>>::Future: Send,
 {
     /// Construct a new `Player`.
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         bind_addr: SocketAddr,
         arena: A,
         mut mining_coordinator: MiningCoordinator,
-        database: Database,
+        database: Database<S>,
+        vm_factory: V,
+        rules: ValidationRules,
+        limits: MempoolLimits,
         radius: usize,
+        codec_limits: Limits,
+        keypair: Keypair,
+        noise_key: Option<Bytes>,
     ) -> Self {
+        assert!(
+            codec_limits.supports_radius(radius),
+            "radius exceeds the message limits"
+        );
+
         // Collect metadata
         let start_time = std::time::SystemTime::now();
         let metadata = Arc::new(Metadata {
             addr: bind_addr.clone(),
             start_time,
+            pubkey: Bytes::copy_from_slice(keypair.public.as_bytes()),
+            listen_addr: bind_addr,
+            radius,
+            static_key: None,
         });
 
-        // TODO: Add pubkey
-        let oddsketch = Bytes::from(vec![0; 4 * radius as usize]);
-        let minisketch = Bytes::from(vec![0; 8 * radius]);
-        let root = Bytes::from(vec![0; DIGEST_LEN]);
-        let site = miner::RawSite::default();
-        let best_nonce = mining_coordinator
-            .call(miner::NewSession(site))
-            .await
-            .unwrap();
-
-        let state_snapshot = StateSnapshot {
-            minisketch,
-            oddsketch,
-            root,
-            best_nonce,
+        let mut state_snapshot = StateSnapshot {
+            mempool: Mempool::new(radius, limits),
+            deferred: Vec::new(),
+            site: Default::default(),
+            best_nonce: Default::default(),
         };
 
-        // Construct V
-        let vm_factory = V::default();
+        // Restore transactions from the database, along with those deferred from the mempool
+        let checkpoint = database
+            .clone()
+            .oneshot(GetCheckpoint)
+            .await
+            .expect("failed to load checkpoint");
+        let stored_txs = database
+            .clone()
+            .oneshot(TransactionsByTime(0..=std::u64::MAX))
+            .await
+            .expect("failed to load transactions");
+        info!("restored {} transactions", stored_txs.len());
+        let deferred = checkpoint
+            .as_ref()
+            .map(|checkpoint| &checkpoint.deferred[..])
+            .unwrap_or_default();
+        state_snapshot.restore(stored_txs, deferred);
+        let now = now_millis();
+        let mut dropped = state_snapshot.mempool.expire(now);
+        dropped.extend(state_snapshot.expire_deferred(now));
+        dropped.extend(state_snapshot.mempool.evict());
+        if !dropped.is_empty() {
+            info!("dropped {} expired or excess transactions", dropped.len());
+            delete_transactions(&database, &dropped)
+                .await
+                .expect("failed to delete transactions");
+        }
+        state_snapshot.site = miner::site(&metadata.pubkey, &state_snapshot.mempool.root());
+
+        // Restore checkpoint, provided it agrees with the stored transactions and our site
+        let best_nonce = match checkpoint {
+            Some(checkpoint)
+                if &checkpoint.oddsketch == state_snapshot.mempool.oddsketch()
+                    && &checkpoint.minisketch == state_snapshot.mempool.minisketch()
+                    && checkpoint.root == state_snapshot.mempool.root()
+                    && checkpoint.site == state_snapshot.site =>
+            {
+                info!("restored checkpoint");
+                mining_coordinator
+                    .call(miner::ResumeSession(checkpoint.site, checkpoint.nonce))
+                    .await
+            }
+            Some(_) => {
+                warn!("checkpoint does not match stored transactions, discarding");
+                mining_coordinator
+                    .call(miner::NewSession(state_snapshot.site))
+                    .await
+            }
+            None => {
+                mining_coordinator
+                    .call(miner::NewSession(state_snapshot.site))
+                    .await
+            }
+        }
+        .unwrap();
+        state_snapshot.best_nonce = best_nonce;
 
         Self {
             arena,
             metadata,
             mining_coordinator,
             database,
-            txs: Default::default(),
             state_snapshot: Arc::new(RwLock::new(state_snapshot)),
             radius,
-            vm_factory,
+            codec_limits,
+            validator: TxValidator::new(rules, vm_factory),
+            keypair: Arc::new(keypair),
+            noise_key,
         }
     }
 
@@ -235,47 +448,236 @@ where
         let mut boxed_listener = Box::pin(filtered_listener);
 
         while let Some(tcp_stream) = boxed_listener.next().await {
-            self.clone().oneshot(NewPeer(tcp_stream)).await;
+            // Handshake concurrently so slow peers don't stall the acceptor
+            let player = self.clone();
+            tokio::spawn(async move {
+                if let Err(NewPeerError::Handshake(err)) =
+                    player.oneshot(NewPeer(tcp_stream, Role::Responder)).await
+                {
+                    warn!("handshake failed; {}", err);
+                }
+            });
         }
     }
 
     /// Begin heartbeat execution.
-    pub async fn begin_heartbeat(self, sample_size: usize, interval_ms: u64) {
+    ///
+    /// A consensus round is run midway through each round period, so that statuses are polled well clear of the
+    /// round boundaries.
+    pub async fn begin_heartbeat(self, sample_size: usize) {
         info!("starting heartbeat");
-        // Poll sample
-        let mut timer = tokio::time::interval(Duration::from_millis(interval_ms));
+        let round_ms = consensus::ROUND_MS;
+        let delay = (round_ms + round_ms / 2 - now_millis() % round_ms) % round_ms;
+        let start = tokio::time::Instant::now() + Duration::from_millis(delay);
+        let mut timer = tokio::time::interval_at(start, Duration::from_millis(round_ms));
         let query = SampleQuery(PollStatus, sample_size);
+        let mut round = Round::new(consensus::current_round());
         while let Some(_) = timer.next().await {
-            // Aggregate results
+            // Skip rounds missed while stalled
+            let current_round = consensus::current_round();
+            if round.id() != current_round {
+                round = Round::new(current_round);
+            }
+            trace!("starting round {}", round.id());
+
+            // Resubmit deferred transactions and drop expired transactions
+            let dropped = {
+                let mut state_snapshot = self.state_snapshot.write().await;
+                for tx in std::mem::take(&mut state_snapshot.deferred) {
+                    state_snapshot.mempool.insert(tx);
+                }
+                let mut dropped = state_snapshot.mempool.expire(now_millis());
+                dropped.extend(state_snapshot.mempool.evict());
+                state_snapshot
+                    .refresh_site(&self.metadata.pubkey, &mut self.mining_coordinator.clone())
+                    .await;
+                dropped
+            };
+            if !dropped.is_empty() {
+                trace!("dropped {} expired or excess transactions", dropped.len());
+                if let Err(err) = delete_transactions(&self.database, &dropped).await {
+                    warn!("failed to delete transactions; {}", err);
+                }
+            }
+
+            // Checkpoint latest nonce
+            let checkpoint = self.state_snapshot.read().await.to_checkpoint();
+            if let Err(err) = self
+                .database
+                .clone()
+                .oneshot(PutCheckpoint(checkpoint))
+                .await
+            {
+                warn!("failed to checkpoint state; {}", err);
+            }
+
+            // Collect entries
             let peer_statuses = self.arena.clone().oneshot(query.clone()).await.unwrap(); // TODO: Don't unwrap
             let (_marker, player_status) = self.clone().oneshot(GetStatus).await.unwrap(); // TODO: Don't unwrap
-            let (addrs, mut peer_entries): (Vec<_>, Vec<_>) = peer_statuses
-                .into_iter()
-                .map(move |(addr, status)| (addr, Entry::from_status(&[], status)))
-                .unzip();
 
-            let my_pubkey = &[];
-            peer_entries.push(Entry::from_status(my_pubkey, player_status));
+            // Only admit entries signed by the peers node key, retaining the statuses for the certificate
+            let mut signed_statuses = Vec::new();
+            for (addr, status) in peer_statuses {
+                let metadata = match self
+                    .arena
+                    .clone()
+                    .oneshot(DirectedQuery(addr, GetMetadata))
+                    .await
+                {
+                    Ok(ok) => ok,
+                    Err(_) => continue,
+                };
+                if !status.verify(&metadata.pubkey) {
+                    warn!("invalid status signature from {}", addr);
+                    continue;
+                }
+                let status_round = status.round;
+                let entry = Entry::from_status(&metadata.pubkey, status.clone());
+                match round.submit(status_round, Some(addr), entry) {
+                    Ok(()) => signed_statuses.push(SignedStatus {
+                        pubkey: metadata.pubkey.clone(),
+                        status,
+                    }),
+                    Err(err) => trace!("ignoring entry from {}; {:?}", addr, err),
+                }
+            }
+
+            let status_round = player_status.round;
+            let entry = Entry::from_status(&self.metadata.pubkey, player_status.clone());
+            match round.submit(status_round, None, entry) {
+                Ok(()) => signed_statuses.push(SignedStatus {
+                    pubkey: self.metadata.pubkey.clone(),
+                    status: player_status,
+                }),
+                Err(err) => trace!("ignoring own entry; {:?}", err),
+            }
 
-            let winning_index = consensus::calculate_winner(&peer_entries[..]).unwrap(); // TODO: Don't unwrap
-            if peer_entries.len() == winning_index + 1 {
-                trace!("player won with {:?}", peer_entries[winning_index]);
-            } else {
-                let addr = addrs[winning_index];
-                trace!(
-                    "{:?} won with {:?}",
-                    addrs[winning_index],
-                    peer_entries[winning_index]
-                );
-                let (minisketch, _) = self.state_snapshot.read().await.to_parts();
-                let reconcile_query = DirectedQuery(addr, Reconcile(minisketch));
-                self.arena.clone().oneshot(reconcile_query).await;
+            // Choose the winner and adopt its state
+            let winner = round.close().unwrap().cloned(); // This is safe
+            match winner {
+                None => trace!("no entries in round {}", round.id()),
+                Some((None, entry)) => trace!("player won with {:?}", entry),
+                Some((Some(addr), entry)) => {
+                    trace!("{:?} won with {:?}", addr, entry);
+                    self.adopt(addr, &entry).await;
+                }
+            }
+
+            // Record the round certificate
+            match RoundCertificate::new(round.id(), signed_statuses) {
+                Ok(certificate) => {
+                    let put_certificate = PutCertificate(round.id(), certificate.to_bytes());
+                    if let Err(err) = self.database.clone().oneshot(put_certificate).await {
+                        warn!("failed to store round certificate; {}", err);
+                    }
+                }
+                Err(err) => trace!("no certificate for round {}; {}", round.id(), err),
             }
+
+            round.finalize().unwrap(); // This is safe
+            round = round.advance().unwrap(); // This is safe
+        }
+    }
+
+    /// Adopt the transaction set of the winner of a round.
+    ///
+    /// Missing transactions are fetched from the winner, while those it lacks are deferred to the next round.
+    async fn adopt(&self, addr: SocketAddr, winner: &Entry) {
+        let (minisketch, status, known) = {
+            let state_snapshot = self.state_snapshot.read().await;
+            let (minisketch, status) = state_snapshot.to_parts();
+            let known: HashSet<TxId> = state_snapshot.mempool.tx_ids().cloned().collect();
+            (minisketch, status, known)
+        };
+        let metadata = match self
+            .arena
+            .clone()
+            .oneshot(DirectedQuery(addr, GetMetadata))
+            .await
+        {
+            Ok(ok) => ok,
+            Err(_) => {
+                warn!("failed to fetch metadata of {}", addr);
+                return;
+            }
+        };
+        let capacity = choose_capacity(
+            &status.oddsketch,
+            &winner.oddsketch,
+            self.radius,
+            metadata.radius,
+        );
+        trace!("reconciling with capacity {}", capacity);
+        let reconcile_query = DirectedQuery(addr, Reconcile(minisketch.truncate(capacity), known));
+        let reconciliation = match self.arena.clone().oneshot(reconcile_query).await {
+            Ok(ok) => ok,
+            Err(_) => {
+                warn!("failed to reconcile with {}", addr);
+                return;
+            }
+        };
+        trace!(
+            "received {} transactions from {}, which is missing {}",
+            reconciliation.txs.len(),
+            addr,
+            reconciliation.missing.len()
+        );
+        for tx in reconciliation.txs {
+            match self.clone().oneshot(tx).await {
+                Ok(()) | Err(MempoolError::Duplicate) | Err(MempoolError::Full) => (),
+                // The sketches cannot distinguish the two, so the transaction cannot be reconciled
+                Err(MempoolError::ShortIdCollision) => {
+                    warn!("short ID collision with transaction from {}", addr)
+                }
+                Err(MempoolError::Storage(err)) => {
+                    warn!("failed to store transaction; {}", err)
+                }
+                Err(err) => trace!("rejected transaction from {}; {}", addr, err),
+            }
+        }
+
+        let checkpoint = {
+            let mut state_snapshot = self.state_snapshot.write().await;
+            let surplus: Vec<TxId> = match &reconciliation.inventory {
+                Some(inventory) => {
+                    let inventory: HashSet<&TxId> = inventory.iter().collect();
+                    state_snapshot
+                        .mempool
+                        .tx_ids()
+                        .filter(|tx_id| !inventory.contains(tx_id))
+                        .cloned()
+                        .collect()
+                }
+                None => reconciliation
+                    .missing
+                    .iter()
+                    .filter_map(|short_id| state_snapshot.mempool.get_by_short_id(*short_id))
+                    .map(Transaction::get_id)
+                    .collect(),
+            };
+            trace!("deferring {} transactions", surplus.len());
+            for tx_id in surplus {
+                if let Some(tx) = state_snapshot.mempool.remove(&tx_id) {
+                    state_snapshot.deferred.push(tx);
+                }
+            }
+            state_snapshot
+                .refresh_site(&self.metadata.pubkey, &mut self.mining_coordinator.clone())
+                .await;
+            state_snapshot.to_checkpoint()
+        };
+        if let Err(err) = self
+            .database
+            .clone()
+            .oneshot(PutCheckpoint(checkpoint))
+            .await
+        {
+            warn!("failed to checkpoint state; {}", err);
         }
     }
 }
 
-impl<A, V> Service<GetStatus> for Player<A, V> {
+impl<A, V, S> Service<GetStatus> for Player<A, V, S> {
     type Response = (Minisketch, Status);
     type Error = MissingStatus;
     type Future = FutResponse<Self::Response, Self::Error>;
@@ -286,9 +688,11 @@ impl<A, V> Service<GetStatus> for Player<A, V> {
 
     fn call(&mut self, _: GetStatus) -> Self::Future {
         let report_inner = self.state_snapshot.clone();
+        let keypair = self.keypair.clone();
         let fut = async move {
             let site = report_inner.read().await;
-            let (minisketch, status) = site.to_parts();
+            let (minisketch, mut status) = site.to_parts();
+            status.sign(&keypair);
             trace!("fetched status from player; {:?}", status);
             Ok((minisketch, status))
         };
@@ -301,7 +705,10 @@ pub enum TransactionError {
     Database(DatabaseError),
 }
 
-impl<A, V> Service<TransactionInv> for Player<A, V> {
+impl<A, V, S> Service<TransactionInv> for Player<A, V, S>
+where
+    S: StorageBackend,
+{
     type Response = Transactions;
     type Error = TransactionError;
     type Future = FutResponse<Self::Response, Self::Error>;
@@ -312,23 +719,113 @@ impl<A, V> Service<TransactionInv> for Player<A, V> {
     }
 
     fn call(&mut self, inv: TransactionInv) -> Self::Future {
-        let tx_ids = inv.tx_ids;
-        let txs: Vec<_> = tx_ids
-            .iter()
-            .filter_map(|tx_id| {
-                let tx_id_arr: [u8; 32] = tx_id[..].try_into().unwrap();
-                self.txs
-                    .get(&tx_id_arr)
-                    .map(move |pair| pair.value().clone())
-            })
-            .collect();
-        let transactions = Transactions { txs };
+        let state_snapshot = self.state_snapshot.clone();
+        let database = self.database.clone();
+        let fut = async move {
+            let mut txs = Vec::with_capacity(inv.tx_ids.len());
+            for tx_id in inv.tx_ids {
+                // Check cache before falling back to database
+                let cached = state_snapshot.read().await.mempool.get(&tx_id).cloned();
+                let tx = match cached {
+                    Some(some) => Some(some),
+                    None => database
+                        .clone()
+                        .oneshot(GetTransaction(tx_id))
+                        .await
+                        .map_err(TransactionError::Database)?,
+                };
+                txs.extend(tx);
+            }
+            Ok(Transactions { txs })
+        };
+        Box::pin(fut)
+    }
+}
+
+/// Fetch the certificate of a past round.
+pub struct GetRoundCertificate(pub RoundId);
+
+impl<A, V, S> Service<GetRoundCertificate> for Player<A, V, S>
+where
+    S: StorageBackend,
+{
+    type Response = Option<RoundCertificate>;
+    type Error = DatabaseError;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, GetRoundCertificate(round): GetRoundCertificate) -> Self::Future {
+        let database = self.database.clone();
+        let fut = async move {
+            match database.oneshot(GetCertificate(round)).await? {
+                Some(raw) => RoundCertificate::from_bytes(raw)
+                    .map(Some)
+                    .ok_or(DatabaseError::Corrupted),
+                None => Ok(None),
+            }
+        };
+        Box::pin(fut)
+    }
+}
 
-        Box::pin(async move { Ok(transactions) })
+impl<A, V, S> Service<ResolveShortIds> for Player<A, V, S> {
+    type Response = ReconcileResponse;
+    type Error = ();
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, ResolveShortIds(short_ids): ResolveShortIds) -> Self::Future {
+        let state_snapshot = self.state_snapshot.clone();
+        let fut = async move {
+            let state_snapshot = state_snapshot.read().await;
+            let mut response = ReconcileResponse {
+                tx_ids: Vec::new(),
+                missing: Vec::new(),
+            };
+            for short_id in short_ids {
+                match state_snapshot.mempool.get_by_short_id(short_id) {
+                    Some(tx) => response.tx_ids.push(tx.get_id()),
+                    None => response.missing.push(short_id),
+                }
+            }
+            Ok(response)
+        };
+        Box::pin(fut)
     }
 }
 
-impl<A, V> Service<GetMetadata> for Player<A, V> {
+impl<A, V, S> Service<GetInventory> for Player<A, V, S> {
+    type Response = TransactionInv;
+    type Error = ();
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _: GetInventory) -> Self::Future {
+        let state_snapshot = self.state_snapshot.clone();
+        let fut = async move {
+            let tx_ids = state_snapshot
+                .read()
+                .await
+                .mempool
+                .iter()
+                .map(Transaction::get_id)
+                .collect();
+            Ok(TransactionInv { tx_ids })
+        };
+        Box::pin(fut)
+    }
+}
+
+impl<A, V, S> Service<GetMetadata> for Player<A, V, S> {
     type Response = Arc<Metadata>;
     type Error = ();
     type Future = FutResponse<Self::Response, Self::Error>;
@@ -344,7 +841,7 @@ impl<A, V> Service<GetMetadata> for Player<A, V> {
     }
 }
 
-impl<A, T, V> Service<ArenaQuery<T>> for Player<A, V>
+impl<A, T, V, S> Service<ArenaQuery<T>> for Player<A, V, S>
 where
     A: Service<T>,
     T: 'static + Send + Sized,
@@ -362,18 +859,20 @@ where
     }
 }
 
-impl<A, V> Service<Transaction> for Player<A, V>
+impl<A, V, S> Service<Transaction> for Player<A, V, S>
 where
+    S: StorageBackend,
     V: Clone + Send + 'static,
-    V: Service<Transaction, Error = VMSpawnError>,
-    <V as Service<Transaction>>::Future: Send,
+    V: Service<Execute, Error = VMSpawnError>,
+    <V as Service<Execute>>::Response: Metered + Stateful + Send,
+    <V as Service<Execute>>::Future: Send + 'static,
 {
     type Response = ();
     type Error = MempoolError;
     type Future = FutResponse<Self::Response, Self::Error>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.vm_factory.poll_ready(cx).map_err(MempoolError::VM)
+        self.validator.poll_ready(cx)
     }
 
     fn call(&mut self, tx: Transaction) -> Self::Future {
@@ -383,44 +882,146 @@ where
         // let vm = self.vm_constructor.call().await;
         // tokio:spawn(vm.run());
 
-        // TODO: Get new root
-
         let state_snapshot = self.state_snapshot.clone();
-        let mut vm_factory = self.vm_factory.clone();
-        let radius = self.radius;
+        let mut validator = self.validator.clone();
+        let database = self.database.clone();
+        let metadata = self.metadata.clone();
+        let mut mining_coordinator = self.mining_coordinator.clone();
         let fut = async move {
-            vm_factory.call(tx.clone()).await;
-
-            let StateSnapshot {
-                oddsketch,
-                minisketch,
-                ..
-            } = &mut *state_snapshot.write().await;
-
-            // Deserialize oddsketch
-            let mut ms = MinisketchCrypto::try_new(64, 0, radius).unwrap(); // This is safe
-            ms.deserialize(&minisketch);
-
-            // Calculate short ID
-            let short_id = tx.get_short_id();
+            {
+                let state_snapshot = state_snapshot.read().await;
+                validator.check(&tx, &state_snapshot.mempool)?;
+            }
+            let execution = validator.call(tx.clone()).await?;
 
-            // Add to minisketch
-            ms.add(short_id);
+            let tx_id = tx.get_id();
+            let (checkpoint, evicted) = {
+                let mut state_snapshot = state_snapshot.write().await;
+                if !state_snapshot.mempool.insert(tx.clone()) {
+                    if state_snapshot.mempool.contains(&tx_id) {
+                        return Err(MempoolError::Duplicate);
+                    }
+                    return Err(MempoolError::ShortIdCollision);
+                }
+                let evicted = state_snapshot.mempool.evict();
+                info!("new oddsketch; {:?}", state_snapshot.mempool.oddsketch());
+                info!("new root; {:?}", state_snapshot.mempool.root());
+                state_snapshot
+                    .refresh_site(&metadata.pubkey, &mut mining_coordinator)
+                    .await;
+                (state_snapshot.to_checkpoint(), evicted)
+            };
 
-            let mut minisketch_raw = vec![0; ms.serialized_size()];
-            ms.serialize(&mut minisketch_raw).unwrap(); // This is safe
-            info!("new oddsketch: {:?}", minisketch_raw);
-            *minisketch = Bytes::from(minisketch_raw);
+            // Persist the transaction and contract state, only once admitted
+            let admitted = !evicted.iter().any(|tx| tx.get_id() == tx_id);
+            if admitted {
+                let execution = PutExecution {
+                    tx,
+                    contract_id: execution.contract_id(),
+                    state: Bytes::copy_from_slice(execution.state()),
+                };
+                database
+                    .clone()
+                    .oneshot(execution)
+                    .await
+                    .map_err(|err| MempoolError::Storage(Box::new(err)))?;
+            }
 
-            // Add to oddsketch
-            let oddsketch_raw = oddsketch.to_vec();
-            let mut new_oddsketch = Oddsketch::new(oddsketch_raw);
-            new_oddsketch.insert(short_id);
-            *oddsketch = Bytes::from(new_oddsketch.to_vec());
-            info!("new oddsketch; {:?}", oddsketch);
+            // Persist state
+            delete_transactions(&database, &evicted)
+                .await
+                .map_err(|err| MempoolError::Storage(Box::new(err)))?;
+            database
+                .oneshot(PutCheckpoint(checkpoint))
+                .await
+                .map_err(|err| MempoolError::Storage(Box::new(err)))?;
 
+            if !admitted {
+                return Err(MempoolError::Full);
+            }
             Ok(())
         };
         Box::pin(fut)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_snapshot() -> StateSnapshot {
+        StateSnapshot {
+            mempool: Mempool::new(8, MempoolLimits::default()),
+            deferred: Vec::new(),
+            site: Default::default(),
+            best_nonce: Default::default(),
+        }
+    }
+
+    #[test]
+    fn restore_deferred() {
+        let txs: Vec<Transaction> = (0..4)
+            .map(|timestamp| Transaction {
+                timestamp,
+                binary: Bytes::from_static(b"binary"),
+                aux_data: Bytes::new(),
+            })
+            .collect();
+        let mut state = state_snapshot();
+        for tx in &txs {
+            state.mempool.insert(tx.clone());
+        }
+
+        // Defer a transaction the winner lacked, which remains stored
+        let deferred = state.mempool.remove(&txs[1].get_id()).unwrap();
+        state.deferred.push(deferred);
+        let checkpoint = state.to_checkpoint();
+
+        // Reloading every stored transaction reproduces the checkpointed sketches
+        let mut restored = state_snapshot();
+        restored.restore(txs.clone(), &checkpoint.deferred);
+        assert_eq!(restored.to_checkpoint(), checkpoint);
+        assert_eq!(restored.deferred, vec![txs[1].clone()]);
+
+        // Without the deferral they would not match
+        let mut naive = state_snapshot();
+        naive.restore(txs.clone(), &[]);
+        assert_ne!(naive.mempool.oddsketch(), &checkpoint.oddsketch);
+
+        // Deferred transactions expire alongside the mempool
+        let expiry = restored.mempool.limits().expiry;
+        assert!(restored
+            .expire_deferred(txs[1].timestamp + expiry)
+            .is_empty());
+        assert_eq!(
+            restored.expire_deferred(txs[1].timestamp + expiry + 1),
+            vec![txs[1].clone()]
+        );
+        assert!(restored.deferred.is_empty());
+    }
+
+    #[test]
+    fn capacity_bounded_by_both_radii() {
+        let oddsketch = vec![0; 64];
+        let mut distant = vec![0; 64];
+        for byte in distant.iter_mut().take(12) {
+            *byte = 0xff;
+        }
+        let estimate = crypto::estimate_difference(&oddsketch, &distant).unwrap();
+        assert!(estimate > 64);
+
+        // A large difference is capped at the smaller radius, whichever side it belongs to
+        assert_eq!(choose_capacity(&oddsketch, &distant, 64, 16), 16);
+        assert_eq!(choose_capacity(&oddsketch, &distant, 16, 64), 16);
+
+        // As is an unestimable difference
+        assert_eq!(choose_capacity(&oddsketch, &[0; 32], 64, 16), 16);
+        assert_eq!(choose_capacity(&oddsketch, &[0; 32], 16, 64), 16);
+
+        // Small differences use the minimum capacity
+        assert_eq!(
+            choose_capacity(&oddsketch, &oddsketch, 64, 16),
+            MIN_CAPACITY
+        );
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::{BTreeSet, HashMap};
+
+use bytes::Bytes;
+use common::network::{ShortId, Transaction, TxId};
+use crypto::{
+    blake3,
+    merkle::{MerkleTree, Mode, Proof},
+    Minisketch as MinisketchCrypto, Oddsketch,
+};
+
+/// Limits on the size of the `Mempool`.
+#[derive(Clone, Copy, Debug)]
+pub struct MempoolLimits {
+    pub max_txs: usize,
+    /// Maximum total length of the canonical transaction encodings.
+    pub max_bytes: usize,
+    /// How long, in milliseconds after its timestamp, a transaction is kept.
+    pub expiry: u64,
+}
+
+impl Default for MempoolLimits {
+    fn default() -> Self {
+        Self {
+            max_txs: 1 << 16,
+            max_bytes: 1 << 28,
+            expiry: 24 * 60 * 60 * 1000,
+        }
+    }
+}
+
+/// The pool of pending transactions.
+///
+/// Transactions are indexed by ID and short ID, the minisketch, oddsketch and Merkle root are kept in sync with
+/// the contents.
+///
+/// When over its limits, the oldest transactions are evicted first.
+#[derive(Clone)]
+pub struct Mempool {
+    txs: HashMap<TxId, Transaction>,
+    short_ids: HashMap<ShortId, TxId>,
+    /// Transaction IDs ordered by timestamp.
+    by_age: BTreeSet<(u64, TxId)>,
+    /// Total length of the canonical transaction encodings.
+    bytes: usize,
+    limits: MempoolLimits,
+    /// Transaction IDs in the order they are committed to by the Merkle root.
+    ids: BTreeSet<TxId>,
+    minisketch: Bytes,
+    oddsketch: Bytes,
+    radius: usize,
+}
+
+impl Mempool {
+    /// Construct an empty `Mempool`.
+    pub fn new(radius: usize, limits: MempoolLimits) -> Self {
+        Self {
+            txs: HashMap::new(),
+            short_ids: HashMap::new(),
+            by_age: BTreeSet::new(),
+            bytes: 0,
+            limits,
+            ids: BTreeSet::new(),
+            minisketch: Bytes::from(vec![0; 8 * radius]),
+            oddsketch: Bytes::from(vec![0; 4 * radius]),
+            radius,
+        }
+    }
+
+    /// Toggle a short ID in both sketches.
+    ///
+    /// Both sketches are linear, toggling a short ID twice leaves them unchanged.
+    fn toggle_short_id(&mut self, ShortId(short_id): ShortId) {
+        // Toggle in minisketch
+        let mut ms = MinisketchCrypto::try_new(64, 0, self.radius).unwrap(); // This is safe
+        ms.deserialize(&self.minisketch);
+        ms.add(short_id);
+        let mut minisketch_raw = vec![0; ms.serialized_size()];
+        ms.serialize(&mut minisketch_raw).unwrap(); // This is safe
+        self.minisketch = Bytes::from(minisketch_raw);
+
+        // Toggle in oddsketch
+        let mut oddsketch = Oddsketch::new(self.oddsketch.to_vec());
+        oddsketch.insert(short_id);
+        self.oddsketch = Bytes::from(oddsketch.to_vec());
+    }
+
+    /// Insert a transaction.
+    ///
+    /// Returns `false`, leaving the mempool unchanged, if the transaction or its short ID is already present.
+    pub fn insert(&mut self, tx: Transaction) -> bool {
+        let tx_id = tx.get_id();
+        let short_id = tx.get_short_id();
+        if self.txs.contains_key(&tx_id) || self.short_ids.contains_key(&short_id) {
+            return false;
+        }
+
+        self.toggle_short_id(short_id);
+        self.short_ids.insert(short_id, tx_id);
+        self.ids.insert(tx_id);
+        self.by_age.insert((tx.timestamp, tx_id));
+        self.bytes += tx.encoded_len();
+        self.txs.insert(tx_id, tx);
+        true
+    }
+
+    /// Remove a transaction by ID.
+    pub fn remove(&mut self, tx_id: &TxId) -> Option<Transaction> {
+        let tx = self.txs.remove(tx_id)?;
+        let short_id = tx.get_short_id();
+        self.short_ids.remove(&short_id);
+        self.ids.remove(tx_id);
+        self.by_age.remove(&(tx.timestamp, *tx_id));
+        self.bytes -= tx.encoded_len();
+        self.toggle_short_id(short_id);
+        Some(tx)
+    }
+
+    /// Remove the oldest transaction.
+    fn remove_oldest(&mut self) -> Option<Transaction> {
+        let (_, tx_id) = *self.by_age.iter().next()?;
+        self.remove(&tx_id)
+    }
+
+    /// Evict the oldest transactions until the mempool is within its count and byte limits.
+    pub fn evict(&mut self) -> Vec<Transaction> {
+        let mut evicted = Vec::new();
+        while self.txs.len() > self.limits.max_txs || self.bytes > self.limits.max_bytes {
+            match self.remove_oldest() {
+                Some(tx) => evicted.push(tx),
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    /// Remove transactions which have expired by `now`, in milliseconds since the Unix epoch.
+    pub fn expire(&mut self, now: u64) -> Vec<Transaction> {
+        let mut expired = Vec::new();
+        while let Some(&(timestamp, _)) = self.by_age.iter().next() {
+            if timestamp.saturating_add(self.limits.expiry) >= now {
+                break;
+            }
+            expired.extend(self.remove_oldest());
+        }
+        expired
+    }
+
+    /// Get a transaction by ID.
+    pub fn get(&self, tx_id: &TxId) -> Option<&Transaction> {
+        self.txs.get(tx_id)
+    }
+
+    /// Get a transaction by short ID.
+    pub fn get_by_short_id(&self, short_id: ShortId) -> Option<&Transaction> {
+        self.short_ids
+            .get(&short_id)
+            .and_then(|tx_id| self.txs.get(tx_id))
+    }
+
+    pub fn contains(&self, tx_id: &TxId) -> bool {
+        self.txs.contains_key(tx_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.txs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.txs.is_empty()
+    }
+
+    /// Total length of the canonical transaction encodings.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn limits(&self) -> &MempoolLimits {
+        &self.limits
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Transaction> {
+        self.txs.values()
+    }
+
+    pub fn tx_ids(&self) -> impl Iterator<Item = &TxId> {
+        self.txs.keys()
+    }
+
+    /// Get the serialized minisketch.
+    pub fn minisketch(&self) -> &Bytes {
+        &self.minisketch
+    }
+
+    /// Get the serialized oddsketch.
+    pub fn oddsketch(&self) -> &Bytes {
+        &self.oddsketch
+    }
+
+    /// Build the domain separated Merkle tree over the ordered transaction IDs.
+    fn tree(&self) -> MerkleTree<'_, blake3::Hasher> {
+        let mut tree = MerkleTree::with_mode(Mode::DomainSeparated);
+        for tx_id in &self.ids {
+            tree.add_leaf(tx_id);
+        }
+        tree.build_tree();
+        tree
+    }
+
+    /// Get the Merkle root of the transaction IDs.
+    ///
+    /// The root commits to the number of transactions, see `crypto::merkle::verify_separated_proof`.
+    pub fn root(&self) -> Bytes {
+        let tree = self.tree();
+        Bytes::copy_from_slice(tree.root().unwrap()) // This is safe
+    }
+
+    /// Construct an inclusion proof of a transaction ID under the Merkle root.
+    pub fn proof(&self, tx_id: &TxId) -> Option<Proof> {
+        if !self.ids.contains(tx_id) {
+            return None;
+        }
+        let index = self.ids.range(..tx_id).count();
+        self.tree().proof(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::merkle::verify_separated_proof;
+
+    fn tx(timestamp: u64) -> Transaction {
+        Transaction {
+            timestamp,
+            binary: Bytes::from_static(b"binary"),
+            aux_data: Bytes::new(),
+        }
+    }
+
+    #[test]
+    fn insert_remove() {
+        let mut mempool = Mempool::new(8, MempoolLimits::default());
+        let empty = (mempool.minisketch().clone(), mempool.oddsketch().clone());
+        let empty_root = mempool.root();
+
+        assert!(mempool.insert(tx(1)));
+        assert!(mempool.insert(tx(2)));
+        assert_eq!(mempool.len(), 2);
+        assert_eq!(mempool.get_by_short_id(tx(1).get_short_id()), Some(&tx(1)));
+        let (minisketch, oddsketch) = (mempool.minisketch().clone(), mempool.oddsketch().clone());
+        let root = mempool.root();
+        assert_ne!(root, empty_root);
+
+        // Duplicates leave the sketches untouched
+        assert!(!mempool.insert(tx(1)));
+        assert_eq!(mempool.minisketch(), &minisketch);
+        assert_eq!(mempool.oddsketch(), &oddsketch);
+        assert_eq!(mempool.root(), root);
+
+        // The root is independent of insertion order
+        let mut reordered = Mempool::new(8, MempoolLimits::default());
+        reordered.insert(tx(2));
+        reordered.insert(tx(1));
+        assert_eq!(reordered.root(), root);
+
+        assert_eq!(mempool.remove(&tx(1).get_id()), Some(tx(1)));
+        assert_eq!(mempool.proof(&tx(1).get_id()), None);
+        assert_eq!(mempool.remove(&tx(2).get_id()), Some(tx(2)));
+        assert_eq!(mempool.remove(&tx(2).get_id()), None);
+        assert!(mempool.is_empty());
+        assert_eq!(
+            (mempool.minisketch().clone(), mempool.oddsketch().clone()),
+            empty
+        );
+        assert_eq!(mempool.root(), empty_root);
+    }
+
+    #[test]
+    fn proofs() {
+        let mut mempool = Mempool::new(8, MempoolLimits::default());
+        for timestamp in 0..5 {
+            mempool.insert(tx(timestamp));
+        }
+        let root = mempool.root();
+        let leaf_count = mempool.len() as u64;
+
+        for timestamp in 0..5 {
+            let tx_id = tx(timestamp).get_id();
+            let proof = mempool.proof(&tx_id).unwrap();
+            assert!(verify_separated_proof::<blake3::Hasher>(
+                &root, &tx_id.0, leaf_count, &proof
+            ));
+        }
+
+        // Proofs do not carry over to other transactions or counts
+        let tx_id = tx(0).get_id();
+        let proof = mempool.proof(&tx_id).unwrap();
+        assert!(!verify_separated_proof::<blake3::Hasher>(
+            &root,
+            &tx(5).get_id().0,
+            leaf_count,
+            &proof
+        ));
+        assert!(!verify_separated_proof::<blake3::Hasher>(
+            &root,
+            &tx_id.0,
+            leaf_count + 1,
+            &proof
+        ));
+        assert_eq!(mempool.proof(&tx(5).get_id()), None);
+    }
+
+    #[test]
+    fn evict_expire() {
+        let limits = MempoolLimits {
+            max_txs: 3,
+            max_bytes: 3 * tx(0).encoded_len(),
+            expiry: 100,
+        };
+        let mut mempool = Mempool::new(8, limits);
+        let empty = (mempool.minisketch().clone(), mempool.oddsketch().clone());
+
+        // The oldest transactions are evicted first
+        for timestamp in &[3, 1, 4, 2] {
+            mempool.insert(tx(*timestamp));
+        }
+        assert_eq!(mempool.evict(), vec![tx(1)]);
+        assert_eq!(mempool.len(), 3);
+        assert!(mempool.evict().is_empty());
+
+        // Larger transactions count toward the byte limit
+        let mut large = tx(5);
+        large.aux_data = Bytes::from(vec![0; tx(0).encoded_len()]);
+        mempool.insert(large.clone());
+        assert_eq!(mempool.evict(), vec![tx(2), tx(3)]);
+        assert_eq!(mempool.bytes(), tx(4).encoded_len() + large.encoded_len());
+
+        assert!(mempool.expire(104).is_empty());
+        assert_eq!(mempool.expire(105), vec![tx(4)]);
+        assert_eq!(mempool.expire(1_000), vec![large]);
+
+        // Sketches remain in sync with the contents
+        assert!(mempool.is_empty());
+        assert_eq!(mempool.bytes(), 0);
+        assert_eq!(
+            (mempool.minisketch().clone(), mempool.oddsketch().clone()),
+            empty
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use std::{net::SocketAddr, pin::Pin, sync::Arc};
 
 use common::{
-    network::{Status, Transactions},
+    network::{Status, TransactionInv, TxId},
     services::*,
     FutResponse,
 };
@@ -12,12 +12,13 @@ use futures_core::{
 };
 use futures_sink::Sink;
 use futures_util::future::AbortHandle;
-use network::Message;
+use network::{codec::Limits, Message};
 use pin_project::pin_project;
 use tokio::sync::RwLock;
 use tokio_tower::pipeline::Client;
 use tower_buffer::Buffer;
 use tower_service::Service;
+use tower_util::ServiceExt;
 use tracing::info;
 
 use super::*;
@@ -94,6 +95,8 @@ pub struct PeerClient {
     last_status: Arc<RwLock<Option<Status>>>,
     client_svc: ClientService,
     terminator: AbortHandle,
+    /// The limits of the codec framing the peer.
+    limits: Limits,
 }
 
 impl PeerClient {
@@ -103,12 +106,14 @@ impl PeerClient {
         last_status: Arc<RwLock<Option<Status>>>,
         client_svc: ClientService,
         terminator: AbortHandle,
+        limits: Limits,
     ) -> Self {
         Self {
             metadata,
             client_svc,
             last_status,
             terminator,
+            limits,
         }
     }
 
@@ -171,7 +176,7 @@ pub enum ReconcileError {
 }
 
 impl Service<Reconcile> for PeerClient {
-    type Response = Transactions;
+    type Response = Reconciliation;
     type Error = ReconcileError;
     type Future = FutResponse<Self::Response, Self::Error>;
 
@@ -181,16 +186,48 @@ impl Service<Reconcile> for PeerClient {
             .map_err(ReconcileError::Tower)
     }
 
-    fn call(&mut self, Reconcile(minisketch): Reconcile) -> Self::Future {
+    fn call(&mut self, Reconcile(minisketch, known): Reconcile) -> Self::Future {
         let response_fut = self.client_svc.call(Message::Reconcile(minisketch));
 
+        let mut client_svc = self.client_svc.clone();
+        let max_txs = self.limits.max_txs_per_request();
         let fut = async move {
-            let response = response_fut.await;
-            match response {
-                Ok(Message::ReconcileResponse(txs)) => Ok(txs),
-                Ok(_) => Err(ReconcileError::UnexpectedResponse),
-                Err(err) => Err(ReconcileError::Tower(err)),
+            let (tx_ids, missing, inventory) = match response_fut.await {
+                Ok(Message::ReconcileResponse(response)) => {
+                    (response.tx_ids, response.missing, None)
+                }
+                // Peer failed to decode the difference, fetch its whole inventory
+                Ok(Message::Inventory(inv)) => (inv.tx_ids.clone(), Vec::new(), Some(inv.tx_ids)),
+                Ok(_) => return Err(ReconcileError::UnexpectedResponse),
+                Err(err) => return Err(ReconcileError::Tower(err)),
+            };
+
+            // Fetch the transactions we are missing, in requests small enough for the responses to fit the limits
+            let tx_ids: Vec<TxId> = tx_ids
+                .into_iter()
+                .filter(|tx_id| !known.contains(tx_id))
+                .collect();
+            let mut txs = Vec::with_capacity(tx_ids.len());
+            for chunk in tx_ids.chunks(max_txs) {
+                let tx_inv = TransactionInv {
+                    tx_ids: chunk.to_vec(),
+                };
+                match client_svc.ready_and().await {
+                    Ok(_) => (),
+                    Err(err) => return Err(ReconcileError::Tower(err)),
+                }
+                match client_svc.call(Message::TransactionInv(tx_inv)).await {
+                    Ok(Message::Transactions(response)) => txs.extend(response.txs),
+                    Ok(_) => return Err(ReconcileError::UnexpectedResponse),
+                    Err(err) => return Err(ReconcileError::Tower(err)),
+                }
             }
+
+            Ok(Reconciliation {
+                txs,
+                missing,
+                inventory,
+            })
         };
         info!("reconciling with peer");
         Box::pin(fut)
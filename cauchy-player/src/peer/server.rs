@@ -108,6 +108,10 @@ pub enum Error {
     TransactionInv(TransactionError),
     Minisketch(MinisketchError),
     UnexpectedReconcile,
+    /// The peer sent a handshake message after the handshake completed.
+    UnexpectedHandshake,
+    /// The requested sketch capacity exceeds our own.
+    UnexpectedCapacity,
 }
 
 impl<Pl> Service<Message> for PeerServer<Pl>
@@ -119,6 +123,12 @@ where
     // Get transaction from player
     Pl: Service<TransactionInv, Response = Transactions, Error = TransactionError>,
     <Pl as Service<TransactionInv>>::Future: Send,
+    // Resolve short IDs against the player mempool
+    Pl: Service<ResolveShortIds, Response = ReconcileResponse, Error = ()>,
+    <Pl as Service<ResolveShortIds>>::Future: Send,
+    // Get inventory from player
+    Pl: Service<GetInventory, Response = TransactionInv, Error = ()>,
+    <Pl as Service<GetInventory>>::Future: Send,
 {
     type Response = Option<Message>;
     type Error = Error;
@@ -147,6 +157,7 @@ where
             match message {
                 // Send responses
                 Message::ReconcileResponse(_)
+                | Message::Inventory(_)
                 | Message::Status(_)
                 | Message::Transactions(_)
                 | Message::Transaction(_) => this
@@ -155,6 +166,7 @@ where
                     .await
                     .map_err(Error::ResponseSend)
                     .map(|_| None),
+                Message::Hello(_) | Message::HelloAck(_) => Err(Error::UnexpectedHandshake),
                 Message::Poll => {
                     let (minisketch, status) = match this.player.call(GetStatus).await {
                         Ok(ok) => ok,
@@ -172,29 +184,51 @@ where
                         .map_err(Error::Transaction)
                 }
                 Message::Reconcile(minisketch) => {
+                    // The peer chooses the capacity, up to our own
+                    let capacity = minisketch.capacity();
+                    if capacity > this.radius {
+                        return Err(Error::UnexpectedCapacity);
+                    }
+
                     let mut perceived_minisketch = this
                         .perception
                         .lock()
                         .await
                         .take()
                         .ok_or(Error::UnexpectedReconcile)?
-                        .hydrate(this.radius)
+                        .hydrate(capacity)
                         .map_err(Error::Minisketch)?;
                     let peer_minisketch =
-                        minisketch.hydrate(this.radius).map_err(Error::Minisketch)?;
+                        minisketch.hydrate(capacity).map_err(Error::Minisketch)?;
                     perceived_minisketch
                         .merge(&peer_minisketch)
                         .map_err(Error::Minisketch)?;
 
-                    let mut elements = vec![0; this.radius];
-                    let n_ele = perceived_minisketch
-                        .decode(&mut elements)
-                        .map_err(Error::Minisketch)?;
-                    info!("sending {} transactions", n_ele);
-
-                    let txs = Transactions { txs: Vec::new() };
-
-                    Ok(Some(Message::Transactions(txs)))
+                    let mut elements = vec![0; capacity];
+                    let n_ele = match perceived_minisketch.decode(&mut elements) {
+                        Ok(ok) => ok,
+                        Err(_) => {
+                            // The difference exceeds the sketch capacity, fall back to full inventory
+                            let inv = this.player.call(GetInventory).await.unwrap(); // This is safe
+                            info!(
+                                "failed to decode minisketch, sending {} transaction IDs",
+                                inv.tx_ids.len()
+                            );
+                            return Ok(Some(Message::Inventory(inv)));
+                        }
+                    };
+                    elements.truncate(n_ele);
+                    let short_ids = elements.into_iter().map(ShortId).collect();
+
+                    // Those present locally are missing from the peer, and vice versa
+                    let response = this.player.call(ResolveShortIds(short_ids)).await.unwrap(); // This is safe
+                    info!(
+                        "sending {} transaction IDs, missing {}",
+                        response.tx_ids.len(),
+                        response.missing.len()
+                    );
+
+                    Ok(Some(Message::ReconcileResponse(response)))
                 }
             }
         };
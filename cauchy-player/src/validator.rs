@@ -0,0 +1,329 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures_core::task::{Context, Poll};
+use tower_service::Service;
+
+use common::{network::Transaction, services::*, FutResponse};
+
+use crate::Mempool;
+
+/// Rules a transaction must satisfy to be admitted to the mempool.
+#[derive(Clone, Copy, Debug)]
+pub struct ValidationRules {
+    pub max_binary_len: usize,
+    pub max_aux_len: usize,
+    /// How far, in milliseconds, a timestamp may be ahead of local time.
+    pub max_future: u64,
+    /// How far, in milliseconds, a timestamp may be behind local time.
+    pub max_age: u64,
+    /// The maximum cost of initializing the contract.
+    pub max_cost: u128,
+}
+
+impl Default for ValidationRules {
+    fn default() -> Self {
+        Self {
+            max_binary_len: 1 << 20,
+            max_aux_len: 1 << 16,
+            max_future: 60_000,
+            max_age: 24 * 60 * 60 * 1000,
+            max_cost: 1 << 32,
+        }
+    }
+}
+
+/// Validates transactions before they are admitted to the mempool.
+///
+/// Cheap checks are performed by `check`, calling the service initializes the contract using the wrapped VM
+/// factory.
+#[derive(Clone)]
+pub struct TxValidator<V> {
+    rules: ValidationRules,
+    vm_factory: V,
+}
+
+/// Milliseconds since the Unix epoch.
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap() // This is safe
+        .as_millis() as u64
+}
+
+impl<V> TxValidator<V> {
+    pub fn new(rules: ValidationRules, vm_factory: V) -> Self {
+        Self { rules, vm_factory }
+    }
+
+    pub fn rules(&self) -> &ValidationRules {
+        &self.rules
+    }
+
+    /// Check the size and timestamp of a transaction, and that it is not already in the mempool.
+    pub fn check(&self, tx: &Transaction, mempool: &Mempool) -> Result<(), MempoolError> {
+        self.check_at(tx, mempool, now_millis())
+    }
+
+    fn check_at(&self, tx: &Transaction, mempool: &Mempool, now: u64) -> Result<(), MempoolError> {
+        let rules = &self.rules;
+        if tx.binary.len() > rules.max_binary_len {
+            return Err(MempoolError::Oversized {
+                field: "binary",
+                len: tx.binary.len(),
+                limit: rules.max_binary_len,
+            });
+        }
+        if tx.aux_data.len() > rules.max_aux_len {
+            return Err(MempoolError::Oversized {
+                field: "aux_data",
+                len: tx.aux_data.len(),
+                limit: rules.max_aux_len,
+            });
+        }
+
+        if tx.timestamp > now.saturating_add(rules.max_future) {
+            return Err(MempoolError::FutureTimestamp {
+                timestamp: tx.timestamp,
+                now,
+            });
+        }
+        if tx.timestamp < now.saturating_sub(rules.max_age) {
+            return Err(MempoolError::StaleTimestamp {
+                timestamp: tx.timestamp,
+                now,
+            });
+        }
+
+        if mempool.contains(&tx.get_id()) {
+            return Err(MempoolError::Duplicate);
+        }
+        if mempool.get_by_short_id(tx.get_short_id()).is_some() {
+            return Err(MempoolError::ShortIdCollision);
+        }
+        Ok(())
+    }
+}
+
+/// Execute the transaction, rejecting it if execution fails or exceeds the cost ceiling.
+///
+/// Nothing is persisted, the execution is returned so its contract state can be stored once the transaction is
+/// admitted.
+///
+/// The VM aborts execution once the ceiling is exceeded, so contracts which fail to terminate are rejected.
+impl<V> Service<Transaction> for TxValidator<V>
+where
+    V: Service<Execute, Error = VMSpawnError>,
+    V::Response: Metered + Send,
+    V::Future: Send + 'static,
+{
+    type Response = V::Response;
+    type Error = MempoolError;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.vm_factory.poll_ready(cx).map_err(MempoolError::VM)
+    }
+
+    fn call(&mut self, tx: Transaction) -> Self::Future {
+        let max_cost = self.rules.max_cost;
+        let fut = self.vm_factory.call(Execute { tx, max_cost });
+        Box::pin(async move {
+            let execution = match fut.await {
+                Ok(execution) => execution,
+                Err(VMSpawnError::Spawn(VMError::OutOfFuel(cost))) => {
+                    return Err(MempoolError::CostExceeded {
+                        cost,
+                        limit: max_cost,
+                    })
+                }
+                Err(err) => return Err(MempoolError::VM(err)),
+            };
+            let cost = execution.cost();
+            if cost > max_cost {
+                return Err(MempoolError::CostExceeded {
+                    cost,
+                    limit: max_cost,
+                });
+            }
+            Ok(execution)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::vm::{DefaultVM, VMFactory};
+    use bytes::Bytes;
+    use database::{Database, MemoryBackend};
+    use tower_util::ServiceExt;
+
+    use super::*;
+    use crate::MempoolLimits;
+
+    const NOW: u64 = 1_000_000_000;
+
+    struct Execution(u128);
+
+    impl Metered for Execution {
+        fn cost(&self) -> u128 {
+            self.0
+        }
+    }
+
+    /// A VM factory which charges the length of the binary.
+    #[derive(Clone)]
+    struct MockVM;
+
+    impl Service<Execute> for MockVM {
+        type Response = Execution;
+        type Error = VMSpawnError;
+        type Future = FutResponse<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, Execute { tx, .. }: Execute) -> Self::Future {
+            Box::pin(async move {
+                if tx.binary.is_empty() {
+                    return Err(VMSpawnError::Spawn(VMError::Unknown));
+                }
+                Ok(Execution(tx.binary.len() as u128))
+            })
+        }
+    }
+
+    fn tx(timestamp: u64, binary_len: usize) -> Transaction {
+        Transaction {
+            timestamp,
+            binary: Bytes::from(vec![0; binary_len]),
+            aux_data: Bytes::new(),
+        }
+    }
+
+    fn validator() -> TxValidator<MockVM> {
+        let rules = ValidationRules {
+            max_binary_len: 64,
+            max_aux_len: 8,
+            max_future: 1_000,
+            max_age: 10_000,
+            max_cost: 32,
+        };
+        TxValidator::new(rules, MockVM)
+    }
+
+    #[test]
+    fn check() {
+        let validator = validator();
+        let mut mempool = Mempool::new(8, MempoolLimits::default());
+
+        assert!(validator.check_at(&tx(NOW, 64), &mempool, NOW).is_ok());
+        match validator.check_at(&tx(NOW, 65), &mempool, NOW) {
+            Err(MempoolError::Oversized { field, len, limit }) => {
+                assert_eq!((field, len, limit), ("binary", 65, 64))
+            }
+            _ => panic!("expected oversized binary"),
+        }
+        let mut aux_tx = tx(NOW, 1);
+        aux_tx.aux_data = Bytes::from(vec![0; 9]);
+        match validator.check_at(&aux_tx, &mempool, NOW) {
+            Err(MempoolError::Oversized { field, .. }) => assert_eq!(field, "aux_data"),
+            _ => panic!("expected oversized aux data"),
+        }
+
+        // Timestamps must lie within the window
+        assert!(validator
+            .check_at(&tx(NOW + 1_000, 1), &mempool, NOW)
+            .is_ok());
+        assert!(validator
+            .check_at(&tx(NOW - 10_000, 1), &mempool, NOW)
+            .is_ok());
+        match validator.check_at(&tx(NOW + 1_001, 1), &mempool, NOW) {
+            Err(MempoolError::FutureTimestamp { timestamp, now }) => {
+                assert_eq!((timestamp, now), (NOW + 1_001, NOW))
+            }
+            _ => panic!("expected future timestamp"),
+        }
+        match validator.check_at(&tx(NOW - 10_001, 1), &mempool, NOW) {
+            Err(MempoolError::StaleTimestamp { .. }) => (),
+            _ => panic!("expected stale timestamp"),
+        }
+
+        mempool.insert(tx(NOW, 1));
+        match validator.check_at(&tx(NOW, 1), &mempool, NOW) {
+            Err(MempoolError::Duplicate) => (),
+            _ => panic!("expected duplicate"),
+        }
+    }
+
+    #[tokio::test]
+    async fn initialize() {
+        let validator = validator();
+
+        assert!(validator.clone().oneshot(tx(NOW, 32)).await.is_ok());
+        match validator.clone().oneshot(tx(NOW, 33)).await {
+            Err(MempoolError::CostExceeded { cost, limit }) => assert_eq!((cost, limit), (33, 32)),
+            _ => panic!("expected cost exceeded"),
+        }
+        match validator.oneshot(tx(NOW, 0)).await {
+            Err(MempoolError::VM(VMSpawnError::Spawn(_))) => (),
+            _ => panic!("expected failed initialization"),
+        }
+    }
+
+    /// Assemble a module exporting a function `() -> i32` with the given code.
+    fn module(export: &str, code: &[u8]) -> Bytes {
+        let mut raw = b"\0asm\x01\0\0\0".to_vec();
+        raw.extend(&[1, 5, 1, 0x60, 0, 1, 0x7f]);
+        raw.extend(&[3, 2, 1, 0]);
+        raw.extend(&[7, export.len() as u8 + 4, 1, export.len() as u8]);
+        raw.extend(export.as_bytes());
+        raw.extend(&[0, 0]);
+        raw.extend(&[10, code.len() as u8 + 3, 1, code.len() as u8 + 1, 0]);
+        raw.extend(code);
+        Bytes::from(raw)
+    }
+
+    #[tokio::test]
+    async fn wasm() {
+        let rules = ValidationRules {
+            max_cost: 1_000,
+            ..Default::default()
+        };
+        let vm_factory = VMFactory::<DefaultVM, _>::new(Database::new(MemoryBackend::default()));
+        let validator = TxValidator::new(rules, vm_factory);
+        let tx = |binary: Bytes| Transaction {
+            timestamp: NOW,
+            binary,
+            aux_data: Bytes::new(),
+        };
+
+        // Returns the completed status
+        let completed = module("init", &[0x41, 0xff, 0x01, 0x0b]);
+        assert!(validator.clone().oneshot(tx(completed)).await.is_ok());
+
+        match validator
+            .clone()
+            .oneshot(tx(Bytes::from_static(b"not wasm")))
+            .await
+        {
+            Err(MempoolError::VM(VMSpawnError::Spawn(VMError::Decode))) => (),
+            _ => panic!("expected invalid module"),
+        }
+        let misnamed = module("main", &[0x41, 0xff, 0x01, 0x0b]);
+        match validator.clone().oneshot(tx(misnamed)).await {
+            Err(MempoolError::VM(VMSpawnError::Spawn(VMError::MissingExport(_)))) => (),
+            _ => panic!("expected missing export"),
+        }
+
+        // Loops forever, and is aborted at the cost ceiling
+        let non_terminating = module("init", &[0x03, 0x40, 0x0c, 0x00, 0x0b, 0x00, 0x0b]);
+        match validator.oneshot(tx(non_terminating)).await {
+            Err(MempoolError::CostExceeded { cost, limit }) => {
+                assert_eq!(limit, 1_000);
+                assert!(cost > limit);
+            }
+            _ => panic!("expected cost exceeded"),
+        }
+    }
+}
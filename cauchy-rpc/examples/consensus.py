@@ -0,0 +1,26 @@
+import argparse
+import grpc
+import consensus_pb2, consensus_pb2_grpc
+
+from time import time
+
+parser = argparse.ArgumentParser(description="Fetch a round certificate.")
+parser.add_argument("--rpc", default="127.0.0.1:2081",
+                    help="RPC address of the node")
+parser.add_argument("--round-ms", type=int, required=True,
+                    help="round duration of the node in milliseconds")
+parser.add_argument("--round", type=int,
+                    help="round to fetch, defaults to the previous round")
+args = parser.parse_args()
+
+with grpc.insecure_channel(args.rpc) as channel:
+    consensus_stub = consensus_pb2_grpc.ConsensusStub(channel)
+
+    # The previous round is the latest which may have completed
+    round_id = args.round
+    if round_id is None:
+        round_id = int(time() * 1000) // args.round_ms - 1
+    print("Certificate for round %d..." % round_id)
+    certificate = consensus_stub.GetCertificate(
+        consensus_pb2.CertificateRequest(round=round_id))
+    print(certificate)
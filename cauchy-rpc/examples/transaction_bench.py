@@ -21,7 +21,7 @@ with grpc.insecure_channel('127.0.0.1:2080') as channel:
         print("Broadcasting transaction...")
         start_time = time()
         transaction = transactions_pb2.Transaction(
-            timestamp=123, binary=binary, aux_data=b"ABCD")
+            timestamp=int(time() * 1000), binary=binary, aux_data=b"ABCD")
         threads = []
         for _ in range(0, 1000):
             # thread = Thread(target = lambda: ())
@@ -20,6 +20,6 @@ with grpc.insecure_channel('127.0.0.1:2080') as channel:
 
         print("Broadcasting transaction...")
         transaction = transactions_pb2.Transaction(
-            timestamp=123, binary=binary, aux_data=b"ABCD")
+            timestamp=int(time() * 1000), binary=binary, aux_data=b"ABCD")
         threads = []
         transactions_stub.BroadcastTransaction(transaction)
@@ -0,0 +1,67 @@
+pub mod gen {
+    tonic::include_proto!("consensus");
+}
+
+use tonic::{Request, Response, Status};
+use tower_service::Service;
+use tower_util::ServiceExt;
+
+use consensus::RoundCertificate;
+use player::GetRoundCertificate;
+
+use gen::consensus_server::Consensus;
+use gen::*;
+
+#[derive(Clone)]
+pub struct ConsensusService<Pl> {
+    player: Pl,
+}
+
+impl<Pl> ConsensusService<Pl> {
+    pub fn new(player: Pl) -> Self {
+        ConsensusService { player }
+    }
+}
+
+#[tonic::async_trait]
+impl<Pl> Consensus for ConsensusService<Pl>
+where
+    Pl: Clone + Send + Sync + 'static,
+    // Get round certificate
+    Pl: Service<GetRoundCertificate, Response = Option<RoundCertificate>>,
+    <Pl as Service<GetRoundCertificate>>::Error: std::fmt::Display,
+    <Pl as Service<GetRoundCertificate>>::Future: Send,
+{
+    async fn get_certificate(
+        &self,
+        request: Request<CertificateRequest>,
+    ) -> Result<Response<CertificateResponse>, Status> {
+        let round = request.into_inner().round;
+        let certificate = self
+            .player
+            .clone()
+            .oneshot(GetRoundCertificate(round))
+            .await
+            .map_err(|err| Status::internal(err.to_string()))?
+            .ok_or_else(|| Status::not_found(format!("no certificate for round {}", round)))?;
+
+        let statuses = certificate
+            .statuses
+            .into_iter()
+            .map(|signed| SignedStatus {
+                pubkey: signed.pubkey.to_vec(),
+                oddsketch: signed.status.oddsketch.to_vec(),
+                root: signed.status.root.to_vec(),
+                nonce: signed.status.nonce,
+                round: signed.status.round,
+                signature: signed.status.signature.to_vec(),
+            })
+            .collect();
+        let response = CertificateResponse {
+            round: certificate.round,
+            statuses,
+            winner: certificate.winner.to_vec(),
+        };
+        Ok(Response::new(response))
+    }
+}
@@ -1,3 +1,4 @@
+pub mod consensus;
 pub mod info;
 pub mod mining;
 pub mod peering;
@@ -15,6 +16,10 @@ use common::{
     services::*,
 };
 
+use ::consensus::RoundCertificate;
+use player::GetRoundCertificate;
+
+use consensus::gen::consensus_server::ConsensusServer;
 use peering::gen::peering_server::PeeringServer;
 use transactions::gen::transactions_server::TransactionsServer;
 
@@ -30,6 +35,7 @@ pub struct RPCBuilder<Pl> {
     peering_service: Option<peering::PeeringService<Pl>>,
     mining_service: Option<mining::MiningService>,
     transactions_service: Option<transactions::TransactionsService<Pl>>,
+    consensus_service: Option<consensus::ConsensusService<Pl>>,
 }
 
 impl<Pl> Default for RPCBuilder<Pl> {
@@ -41,6 +47,7 @@ impl<Pl> Default for RPCBuilder<Pl> {
             peering_service: None,
             mining_service: None,
             transactions_service: None,
+            consensus_service: None,
         }
     }
 }
@@ -94,6 +101,12 @@ impl<Pl> RPCBuilder<Pl> {
         self.transactions_service = Some(tx_service);
         self
     }
+
+    pub fn consensus_service(mut self, player: Pl) -> Self {
+        let consensus_service = consensus::ConsensusService::new(player);
+        self.consensus_service = Some(consensus_service);
+        self
+    }
 }
 
 impl<Pl> RPCBuilder<Pl>
@@ -121,6 +134,10 @@ where
     // Broadcast transaction
     Pl: Service<Transaction, Error = MempoolError>,
     <Pl as Service<Transaction>>::Future: Send,
+    // Get round certificate
+    Pl: Service<GetRoundCertificate, Response = Option<RoundCertificate>>,
+    <Pl as Service<GetRoundCertificate>>::Error: std::fmt::Display,
+    <Pl as Service<GetRoundCertificate>>::Future: Send,
 {
     pub async fn start(self, addr: SocketAddr) -> Result<(), TransportError> {
         let mut builder = Server::builder().tcp_keepalive(self.keep_alive);
@@ -131,11 +148,15 @@ where
         let transactions_service = self
             .transactions_service
             .expect("transaction service is required");
+        let consensus_service = self
+            .consensus_service
+            .expect("consensus service is required");
         let router = builder
             .add_service(info_service.into_server())
             .add_service(PeeringServer::new(peering_service))
             .add_service(mining_service.into_server())
-            .add_service(TransactionsServer::new(transactions_service));
+            .add_service(TransactionsServer::new(transactions_service))
+            .add_service(ConsensusServer::new(consensus_service));
 
         if let Some(shutdown_signal) = self.shutdown_signal {
             router
@@ -93,6 +93,7 @@ where
             oddsketch: status.oddsketch.to_vec(),
             root: status.root.to_vec(),
             nonce: status.nonce,
+            round: status.round,
         };
         info!("poll response {:?}", poll_response);
         Ok(Response::new(poll_response))
@@ -107,11 +108,12 @@ where
             .map_err(|err| tonic::Status::invalid_argument(err.to_string()))?;
         self.player
             .clone()
-            .oneshot(NewPeer(tcp_stream))
+            .oneshot(NewPeer(tcp_stream, Role::Initiator))
             .await
             .map_err(|err| match err {
                 NewPeerError::Arena(_err) => tonic::Status::failed_precondition("maximum peers"),
                 NewPeerError::Network(err) => tonic::Status::invalid_argument(err.to_string()),
+                NewPeerError::Handshake(err) => tonic::Status::failed_precondition(err.to_string()),
             })?;
 
         Ok(Response::new(()))
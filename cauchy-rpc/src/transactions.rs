@@ -7,7 +7,7 @@ use tonic::{Request, Response};
 use tower_service::Service;
 use tower_util::ServiceExt;
 
-use common::network::Transaction as TransactionMsg;
+use common::{network::Transaction as TransactionMsg, services::MempoolError};
 
 use gen::transactions_server::Transactions;
 use gen::*;
@@ -28,7 +28,7 @@ impl<Pl> Transactions for TransactionsService<Pl>
 where
     Pl: Clone + Send + Sync + 'static,
     // Broadcast transaction
-    Pl: Service<TransactionMsg>,
+    Pl: Service<TransactionMsg, Error = MempoolError>,
     <Pl as Service<TransactionMsg>>::Future: Send,
 {
     async fn broadcast_transaction(
@@ -46,7 +46,15 @@ where
             .clone()
             .oneshot(tx_msg)
             .await
-            .map_err(|_err| tonic::Status::unknown("todo"))?;
+            .map_err(|err| match err {
+                MempoolError::Duplicate => tonic::Status::already_exists(err.to_string()),
+                MempoolError::ShortIdCollision => {
+                    tonic::Status::failed_precondition(err.to_string())
+                }
+                MempoolError::Full => tonic::Status::resource_exhausted(err.to_string()),
+                MempoolError::Storage(err) => tonic::Status::internal(err.to_string()),
+                err => tonic::Status::invalid_argument(err.to_string()),
+            })?;
         Ok(Response::new(()))
     }
 }
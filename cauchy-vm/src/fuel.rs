@@ -0,0 +1,171 @@
+use common::services::VMError;
+use parity_wasm::elements::{
+    BlockType, CodeSection, ExportEntry, ExportSection, Func, FuncBody, FunctionSection,
+    FunctionType, GlobalEntry, GlobalSection, GlobalType, InitExpr, Instruction, Instructions,
+    Internal, Module, Section, Type, TypeSection, ValueType,
+};
+
+/// The exported function returning the remaining fuel of an instrumented module.
+pub const FUEL_EXPORT: &str = "__fuel";
+
+/// The fuel a module is instrumented with, clamped to fit the fuel global.
+pub fn initial_fuel(max_cost: u128) -> i64 {
+    max_cost.min(std::i64::MAX as u128) as i64
+}
+
+/// Charge `cost` to the fuel global, trapping once it is exhausted.
+fn charge(global: u32, cost: usize) -> Vec<Instruction> {
+    vec![
+        Instruction::GetGlobal(global),
+        Instruction::I64Const(cost as i64),
+        Instruction::I64Sub,
+        Instruction::SetGlobal(global),
+        Instruction::GetGlobal(global),
+        Instruction::I64Const(0),
+        Instruction::I64LtS,
+        Instruction::If(BlockType::NoResult),
+        Instruction::Unreachable,
+        Instruction::End,
+    ]
+}
+
+/// Insert a charge on entry to a function body and at the head of each loop.
+///
+/// A function charges the length of its body, and a loop charges the length of its body each iteration.
+/// Instructions are only revisited by calling a function or branching to a loop, so each instruction executed is
+/// paid for.
+fn meter_body(global: u32, body: &mut FuncBody) {
+    let code = body.code().elements();
+
+    // Find the length of each loop body
+    let mut loop_lens = vec![0; code.len()];
+    let mut open = Vec::new();
+    for (index, instruction) in code.iter().enumerate() {
+        match instruction {
+            Instruction::Block(_) | Instruction::If(_) => open.push(None),
+            Instruction::Loop(_) => open.push(Some(index)),
+            Instruction::End => {
+                if let Some(Some(start)) = open.pop() {
+                    loop_lens[start] = index - start - 1;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let mut metered = charge(global, code.len());
+    for (instruction, loop_len) in code.iter().zip(loop_lens) {
+        metered.push(instruction.clone());
+        if let Instruction::Loop(_) = instruction {
+            metered.extend(charge(global, loop_len));
+        }
+    }
+    *body.code_mut() = Instructions::new(metered);
+}
+
+fn ensure_section<F>(module: &mut Module, has_section: F, section: Section) -> Result<(), VMError>
+where
+    F: Fn(&Module) -> bool,
+{
+    if !has_section(module) {
+        module
+            .insert_section(section)
+            .map_err(|_| VMError::Decode)?;
+    }
+    Ok(())
+}
+
+/// Instrument a module so that it traps once it has consumed more than `max_cost` fuel.
+///
+/// The fuel is held in a new mutable global, whose value is exported through the function `FUEL_EXPORT`.
+pub fn instrument(binary: &[u8], max_cost: u128) -> Result<Vec<u8>, VMError> {
+    let mut module = Module::from_bytes(binary).map_err(|_| VMError::Decode)?;
+    let reserved = module
+        .export_section()
+        .map(|section| {
+            section
+                .entries()
+                .iter()
+                .any(|entry| entry.field() == FUEL_EXPORT)
+        })
+        .unwrap_or(false);
+    if reserved {
+        return Err(VMError::Decode);
+    }
+
+    ensure_section(
+        &mut module,
+        |module| module.type_section().is_some(),
+        Section::Type(TypeSection::default()),
+    )?;
+    ensure_section(
+        &mut module,
+        |module| module.function_section().is_some(),
+        Section::Function(FunctionSection::default()),
+    )?;
+    ensure_section(
+        &mut module,
+        |module| module.global_section().is_some(),
+        Section::Global(GlobalSection::default()),
+    )?;
+    ensure_section(
+        &mut module,
+        |module| module.export_section().is_some(),
+        Section::Export(ExportSection::default()),
+    )?;
+    ensure_section(
+        &mut module,
+        |module| module.code_section().is_some(),
+        Section::Code(CodeSection::default()),
+    )?;
+
+    // Allocate the fuel global and its getter
+    let global = module.globals_space() as u32;
+    let getter = module.functions_space() as u32;
+    let getter_type = module.type_section().unwrap().types().len() as u32; // This is safe
+    let init = InitExpr::new(vec![
+        Instruction::I64Const(initial_fuel(max_cost)),
+        Instruction::End,
+    ]);
+
+    let bodies = module.code_section_mut().unwrap().bodies_mut(); // This is safe
+    for body in bodies.iter_mut() {
+        meter_body(global, body);
+    }
+    bodies.push(FuncBody::new(
+        Vec::new(),
+        Instructions::new(vec![Instruction::GetGlobal(global), Instruction::End]),
+    ));
+
+    module
+        .global_section_mut()
+        .unwrap() // This is safe
+        .entries_mut()
+        .push(GlobalEntry::new(
+            GlobalType::new(ValueType::I64, true),
+            init,
+        ));
+    module
+        .type_section_mut()
+        .unwrap() // This is safe
+        .types_mut()
+        .push(Type::Function(FunctionType::new(
+            Vec::new(),
+            Some(ValueType::I64),
+        )));
+    module
+        .function_section_mut()
+        .unwrap() // This is safe
+        .entries_mut()
+        .push(Func::new(getter_type));
+    module
+        .export_section_mut()
+        .unwrap() // This is safe
+        .entries_mut()
+        .push(ExportEntry::new(
+            FUEL_EXPORT.to_string(),
+            Internal::Function(getter),
+        ));
+
+    module.to_bytes().map_err(|_| VMError::Decode)
+}
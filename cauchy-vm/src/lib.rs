@@ -1,20 +1,27 @@
+mod fuel;
 mod wasm_vm;
 
 use common::{
-    network::Transaction,
-    services::{VMError, VMSpawnError},
+    network::{Transaction, TxId},
+    services::{Execute, Metered, Stateful, VMError, VMSpawnError},
     FutResponse,
 };
+use database::{Database, GetContractState, GetTransaction, StorageBackend};
 
 pub use crypto::merkle::*;
 use futures_core::task::{Context, Poll};
 use tower_service::Service;
+use tower_util::ServiceExt;
 pub use wasm_vm::WasmVM as DefaultVM;
 
 pub struct Script<'a> {
     pub func: Option<&'a str>,
     pub script: Vec<u8>,
     pub aux_data: Option<Vec<u8>>,
+    /// Contract state returned by a previous call.
+    pub state: Option<Vec<u8>>,
+    /// The cost at which execution is aborted.
+    pub max_cost: u128,
 }
 
 impl<'a> From<Transaction> for Script<'a> {
@@ -23,6 +30,8 @@ impl<'a> From<Transaction> for Script<'a> {
             func: None,
             script: tx.binary.to_vec(),
             aux_data: Some(tx.aux_data.to_vec()),
+            state: None,
+            max_cost: std::u128::MAX,
         }
     }
 }
@@ -39,6 +48,7 @@ pub enum ScriptStatus {
 pub struct RetVal {
     cost: u128,
     script_status: ScriptStatus,
+    state: Vec<u8>,
 }
 
 impl RetVal {
@@ -48,6 +58,38 @@ impl RetVal {
     pub fn cost(&self) -> u128 {
         self.cost
     }
+    pub fn state(&self) -> &[u8] {
+        &self.state
+    }
+}
+
+impl Metered for RetVal {
+    fn cost(&self) -> u128 {
+        self.cost
+    }
+}
+
+/// The result of executing a transaction against a contract.
+pub struct Execution {
+    /// ID of the transaction which deployed the contract.
+    pub contract_id: TxId,
+    pub ret_val: RetVal,
+}
+
+impl Metered for Execution {
+    fn cost(&self) -> u128 {
+        self.ret_val.cost
+    }
+}
+
+impl Stateful for Execution {
+    fn contract_id(&self) -> TxId {
+        self.contract_id
+    }
+
+    fn state(&self) -> &[u8] {
+        &self.ret_val.state
+    }
 }
 
 /// Get crate version.
@@ -64,13 +106,36 @@ pub trait CauchyVM {
     ) -> Result<RetVal, VMError>;
 }
 
-#[derive(Clone, Default)]
-pub struct VMFactory<V> {
+/// Spawns VMs, running each contract against the state stored under the ID of its deploying transaction.
+///
+/// Deployments initialize a new contract, while messages, transactions with an empty binary, are passed to the
+/// inbox of the contract they address. The resulting state is not persisted, that is left to the caller once the
+/// transaction is admitted.
+#[derive(Clone)]
+pub struct VMFactory<V, B> {
+    database: Database<B>,
     _vm: std::marker::PhantomData<V>,
 }
 
-impl<V: CauchyVM> Service<Transaction> for VMFactory<V> {
-    type Response = RetVal;
+impl<V, B> VMFactory<V, B> {
+    pub fn new(database: Database<B>) -> Self {
+        Self {
+            database,
+            _vm: Default::default(),
+        }
+    }
+}
+
+fn storage_error(err: database::Error) -> VMSpawnError {
+    VMSpawnError::Storage(Box::new(err))
+}
+
+impl<V, B> Service<Execute> for VMFactory<V, B>
+where
+    V: CauchyVM + Default + Send + 'static,
+    B: StorageBackend,
+{
+    type Response = Execution;
     type Error = VMSpawnError;
     type Future = FutResponse<Self::Response, Self::Error>;
 
@@ -78,14 +143,86 @@ impl<V: CauchyVM> Service<Transaction> for VMFactory<V> {
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, tx: Transaction) -> Self::Future {
-        let new_script = Script::from(tx);
+    fn call(&mut self, Execute { tx, max_cost }: Execute) -> Self::Future {
+        if let Some((contract_id, message)) = tx.message() {
+            let msg = ContractMessage {
+                contract_id,
+                message: Some(message.to_vec()),
+                max_cost,
+            };
+            return self.call(msg);
+        }
+
+        let contract_id = tx.get_id();
+        let mut new_script = Script::from(tx);
+        new_script.max_cost = max_cost;
         let fut = async move {
-            tokio::task::spawn_blocking(move || {
+            let ret_val = tokio::task::spawn_blocking(move || {
                 V::initialize(&new_script).map_err(VMSpawnError::Spawn)
             })
             .await
-            .unwrap()
+            .map_err(|_| VMSpawnError::Aborted)??;
+            Ok(Execution {
+                contract_id,
+                ret_val,
+            })
+        };
+        Box::pin(fut)
+    }
+}
+
+/// A message addressed to a deployed contract.
+pub struct ContractMessage {
+    /// ID of the transaction which deployed the contract.
+    pub contract_id: TxId,
+    pub message: Option<Vec<u8>>,
+    /// The cost at which execution is aborted.
+    pub max_cost: u128,
+}
+
+impl<V, B> Service<ContractMessage> for VMFactory<V, B>
+where
+    V: CauchyVM + Default + Send + 'static,
+    B: StorageBackend,
+{
+    type Response = Execution;
+    type Error = VMSpawnError;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, msg: ContractMessage) -> Self::Future {
+        let database = self.database.clone();
+        let fut = async move {
+            // Load contract and its state
+            let tx = database
+                .clone()
+                .oneshot(GetTransaction(msg.contract_id))
+                .await
+                .map_err(storage_error)?
+                .ok_or(VMSpawnError::MissingContract)?;
+            let state = database
+                .oneshot(GetContractState(msg.contract_id))
+                .await
+                .map_err(storage_error)?;
+            let mut script = Script::from(tx);
+            script.state = state.map(|state| state.to_vec());
+            script.max_cost = msg.max_cost;
+
+            let message = msg.message;
+            let ret_val = tokio::task::spawn_blocking(move || {
+                V::default()
+                    .process_inbox(&script, message)
+                    .map_err(VMSpawnError::Spawn)
+            })
+            .await
+            .map_err(|_| VMSpawnError::Aborted)??;
+            Ok(Execution {
+                contract_id: msg.contract_id,
+                ret_val,
+            })
         };
         Box::pin(fut)
     }
@@ -100,3 +237,143 @@ Handle backpressure at the level of the VMFactoryFactory AND VMFactory
 
 Peer - tx -> (handle backpressure) Player - ??? -> VMFactoryFactory
 */
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use database::{MemoryBackend, PutExecution};
+
+    use super::*;
+
+    /// A VM whose state is its auxiliary data followed by each message received.
+    #[derive(Clone, Default)]
+    struct MockVM;
+
+    fn ret_val(state: Vec<u8>) -> RetVal {
+        RetVal {
+            cost: state.len() as u128,
+            script_status: ScriptStatus::Completed,
+            state,
+        }
+    }
+
+    impl CauchyVM for MockVM {
+        fn initialize(script: &Script<'_>) -> Result<RetVal, VMError> {
+            Ok(ret_val(script.aux_data.clone().unwrap_or_default()))
+        }
+
+        fn process_inbox(
+            &mut self,
+            script: &Script<'_>,
+            message: Option<Vec<u8>>,
+        ) -> Result<RetVal, VMError> {
+            let mut state = script.state.clone().ok_or(VMError::BadState)?;
+            state.extend(message.unwrap_or_default());
+            Ok(ret_val(state))
+        }
+    }
+
+    fn execute(tx: Transaction) -> Execute {
+        Execute {
+            tx,
+            max_cost: 1_000,
+        }
+    }
+
+    fn message(contract_id: TxId, message: &[u8]) -> Execute {
+        execute(Transaction {
+            timestamp: 0,
+            binary: Bytes::new(),
+            aux_data: [contract_id.as_ref(), message].concat().into(),
+        })
+    }
+
+    /// Persist a transaction along with the state resulting from its execution, as if it were admitted.
+    async fn persist(database: &Database<MemoryBackend>, tx: Transaction, execution: &Execution) {
+        database
+            .clone()
+            .oneshot(PutExecution {
+                tx,
+                contract_id: execution.contract_id(),
+                state: Bytes::copy_from_slice(execution.state()),
+            })
+            .await
+            .unwrap();
+    }
+
+    /// Execute a transaction and persist it.
+    async fn admit(
+        vm_factory: &VMFactory<MockVM, MemoryBackend>,
+        database: &Database<MemoryBackend>,
+        execute: Execute,
+    ) -> Result<Execution, VMSpawnError> {
+        let tx = execute.tx.clone();
+        let execution = vm_factory.clone().oneshot(execute).await?;
+        persist(database, tx, &execution).await;
+        Ok(execution)
+    }
+
+    async fn contract_state(
+        database: &Database<MemoryBackend>,
+        contract_id: TxId,
+    ) -> Option<Bytes> {
+        database
+            .clone()
+            .oneshot(GetContractState(contract_id))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn isolated() {
+        let database = Database::new(MemoryBackend::default());
+        let vm_factory = VMFactory::<MockVM, _>::new(database.clone());
+
+        // Deploy two contracts
+        let mut contract_ids = Vec::new();
+        for aux_data in &[&b"a"[..], &b"b"[..]] {
+            let tx = Transaction {
+                timestamp: 0,
+                binary: Bytes::from_static(b"binary"),
+                aux_data: Bytes::copy_from_slice(aux_data),
+            };
+            contract_ids.push(tx.get_id());
+            let execution = vm_factory
+                .clone()
+                .oneshot(execute(tx.clone()))
+                .await
+                .unwrap();
+
+            // Nothing is persisted until the transaction is admitted
+            assert_eq!(contract_state(&database, tx.get_id()).await, None);
+            persist(&database, tx, &execution).await;
+        }
+        let (id_a, id_b) = (contract_ids[0], contract_ids[1]);
+
+        // Messages only reach the contract they address
+        let execution = admit(&vm_factory, &database, message(id_a, b"1"))
+            .await
+            .unwrap();
+        assert_eq!(execution.contract_id(), id_a);
+        assert_eq!(execution.state(), b"a1");
+        admit(&vm_factory, &database, message(id_b, b"2"))
+            .await
+            .unwrap();
+        admit(&vm_factory, &database, message(id_a, b"3"))
+            .await
+            .unwrap();
+        assert_eq!(
+            contract_state(&database, id_a).await,
+            Some(Bytes::from_static(b"a13"))
+        );
+        assert_eq!(
+            contract_state(&database, id_b).await,
+            Some(Bytes::from_static(b"b2"))
+        );
+
+        match vm_factory.oneshot(message(TxId([0; 32]), b"4")).await {
+            Err(VMSpawnError::MissingContract) => (),
+            _ => panic!("expected missing contract"),
+        }
+    }
+}
@@ -1,4 +1,7 @@
-use super::{CauchyVM, RetVal, Script, ScriptStatus};
+use super::{
+    fuel::{self, FUEL_EXPORT},
+    CauchyVM, RetVal, Script, ScriptStatus,
+};
 
 use common::services::VMError;
 use rust_wasm::values::Value;
@@ -53,8 +56,10 @@ impl WasmVM {
         } else {
             "inbox"
         };
-        let mut store = init_store();
-        restore_store(&mut store, "some_txid");
+        let store = match &script.state {
+            Some(state) => bincode::deserialize(state).map_err(|_| VMError::BadState)?,
+            None => init_store(),
+        };
         Self::call_func(script, store, func, message)
     }
 
@@ -64,29 +69,47 @@ impl WasmVM {
         func: &str,
         message: Option<Vec<u8>>,
     ) -> Result<RetVal> {
-        let module = decode_module(Cursor::new(&script.script)).unwrap();
-        let module_instance = instantiate_module(&mut store, module, &[]).unwrap();
-        if let ExternVal::Func(main_addr) = get_export(&module_instance, func).unwrap() {
-            let res = invoke_func(
-                &mut store,
-                main_addr,
-                Vec::new(),
-                script.aux_data.as_ref(),
-                message.as_ref(),
-            );
-            // println!("func '{}' returned {:X?}", func, res);
-            match res {
-                Ok(v) if v.0.len() == 1 => {
-                    save_store("some_txid", &store);
-                    Ok(RetVal {
-                        cost: v.1,
-                        script_status: ScriptStatus::try_from(v.0[0])?,
-                    })
-                }
-                _ => Err(VMError::Unknown),
-            }
-        } else {
-            Err(VMError::Unknown)
+        let binary = fuel::instrument(&script.script, script.max_cost)?;
+        let module = decode_module(Cursor::new(&binary)).map_err(|_| VMError::Decode)?;
+        let module_instance =
+            instantiate_module(&mut store, module, &[]).map_err(|_| VMError::Instantiate)?;
+        let main_addr = match get_export(&module_instance, func) {
+            Ok(ExternVal::Func(addr)) => addr,
+            _ => return Err(VMError::MissingExport(func.to_string())),
+        };
+        let fuel_addr = match get_export(&module_instance, FUEL_EXPORT) {
+            Ok(ExternVal::Func(addr)) => addr,
+            _ => return Err(VMError::MissingExport(FUEL_EXPORT.to_string())),
+        };
+
+        let res = invoke_func(
+            &mut store,
+            main_addr,
+            Vec::new(),
+            script.aux_data.as_ref(),
+            message.as_ref(),
+        );
+
+        // Read the remaining fuel, which remains readable after a trap
+        let remaining = match invoke_func(&mut store, fuel_addr, Vec::new(), None, None) {
+            Ok((values, _)) => match values[..] {
+                [Value::I64(remaining)] => remaining as i64,
+                _ => return Err(VMError::Unknown),
+            },
+            Err(_) => return Err(VMError::Unknown),
+        };
+        let cost = (fuel::initial_fuel(script.max_cost) as i128 - remaining as i128) as u128;
+        if remaining < 0 {
+            return Err(VMError::OutOfFuel(cost));
+        }
+
+        match res {
+            Ok(v) if v.0.len() == 1 => Ok(RetVal {
+                cost,
+                script_status: ScriptStatus::try_from(v.0[0])?,
+                state: bincode::serialize(&store).map_err(|_| VMError::Unknown)?,
+            }),
+            _ => Err(VMError::Unknown),
         }
     }
 }
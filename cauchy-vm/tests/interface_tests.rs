@@ -9,13 +9,16 @@ mod vm_tests {
     fn vm_interface() {
         let aux_data = Some(vec![0xEF, 0xBE, 0xAD, 0xDE, 0x45]);
 
-        let script = Script {
+        let mut script = Script {
             func: None,
             script: Vec::from(BASIC_WASM),
             aux_data,
+            state: None,
+            max_cost: 1 << 32,
         };
         let res = DefaultVM::initialize(&script).unwrap();
         assert_eq!(res.status(), &ScriptStatus::Killed);
+        script.state = Some(res.state().to_vec());
         let res = DefaultVM::process_inbox(&script, None).unwrap();
         assert_eq!(res.status(), &ScriptStatus::Completed);
     }
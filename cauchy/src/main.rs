@@ -2,6 +2,8 @@ pub mod settings;
 
 use std::net::SocketAddr;
 
+use bytes::Bytes;
+use database::{Database, MemoryBackend, StorageBackend};
 use settings::*;
 use vm::{DefaultVM, VMFactory};
 
@@ -24,6 +26,17 @@ async fn main() {
     // Collect settings
     let settings = Settings::new(matches).expect("failed to collect settings");
 
+    // Construct database
+    match settings.storage {
+        Storage::Disk => {
+            let database = Database::open(&settings.data_dir).expect("failed to open database");
+            run(settings, database).await
+        }
+        Storage::Memory => run(settings, Database::new(MemoryBackend::default())).await,
+    }
+}
+
+async fn run<S: StorageBackend>(settings: Settings, database: Database<S>) {
     // Create miners
     let miner = miner::MiningCoordinator::new(settings.mining_threads);
 
@@ -31,14 +44,29 @@ async fn main() {
     let arena: arena::Arena = arena::Arena::default();
 
     // Construct player
-    let database = database::Database::default();
     let bind_addr: SocketAddr = settings.bind.parse().expect("failed to parse bind address");
-    let player = player::Player::<_, VMFactory<DefaultVM>>::new(
+    let vm_factory = VMFactory::<DefaultVM, _>::new(database.clone());
+    let keypair =
+        crypto::load_keypair(&settings.identity_key).expect("failed to load identity key");
+    let noise_key = if settings.noise {
+        let noise_key =
+            network::load_static_key(&settings.noise_key).expect("failed to load noise key");
+        Some(Bytes::from(noise_key))
+    } else {
+        None
+    };
+    let player = player::Player::new(
         bind_addr,
         arena,
         miner.clone(),
         database,
+        vm_factory,
+        player::ValidationRules::default(),
+        player::MempoolLimits::default(),
         settings.radius,
+        settings.limits(),
+        keypair,
+        noise_key,
     )
     .await;
 
@@ -59,6 +87,7 @@ async fn main() {
         )
         .mining_service(miner)
         .transactions_service(player.clone())
+        .consensus_service(player.clone())
         .start(rpc_addr);
 
     // Peer acceptor task
@@ -66,7 +95,7 @@ async fn main() {
     tokio::spawn(rpc_server);
 
     // Peer polling task
-    let peer_poll = player.begin_heartbeat(3, 10_000);
+    let peer_poll = player.begin_heartbeat(3);
     tokio::spawn(peer_poll);
 
     peer_acceptor.await;
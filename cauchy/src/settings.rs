@@ -1,5 +1,6 @@
 use clap::{crate_authors, crate_description, crate_name, crate_version, App, Arg, ArgMatches};
 use config::{Config, ConfigError, File};
+use network::codec::Limits;
 use serde::Deserialize;
 
 const FOLDER_DIR: &str = ".cauchy";
@@ -35,15 +36,62 @@ pub fn app_init_and_matches<'a>() -> ArgMatches<'a> {
                 .help("Number of mining threads")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("storage")
+                .long("storage")
+                .help("Sets the storage backend")
+                .possible_values(&["disk", "memory"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("data-dir")
+                .long("data-dir")
+                .help("Sets the data directory")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("identity-key")
+                .long("identity-key")
+                .help("Sets the node identity key path")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("noise")
+                .long("noise")
+                .help("Encrypts peer connections using Noise"),
+        )
+        .arg(
+            Arg::with_name("noise-key")
+                .long("noise-key")
+                .help("Sets the Noise static key path")
+                .takes_value(true),
+        )
         .get_matches()
 }
 
+/// The storage backend used by the player.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Storage {
+    Disk,
+    Memory,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     pub bind: String,
     pub rpc_bind: String,
     pub radius: usize,
+    /// Maximum length of a peers oddsketch, must admit `4 * radius`.
+    pub max_oddsketch_len: usize,
+    /// Maximum minisketch capacity accepted from peers, must admit `radius`.
+    pub max_capacity: usize,
     pub mining_threads: u16,
+    pub data_dir: String,
+    pub storage: Storage,
+    pub identity_key: String,
+    pub noise: bool,
+    pub noise_key: String,
 }
 
 impl Settings {
@@ -60,7 +108,21 @@ impl Settings {
         s.set_default("bind", "127.0.0.1:1080")?;
         s.set_default("rpc_bind", "0.0.0.0:2080")?;
         s.set_default("radius", 128)?;
+        let limits = Limits::default();
+        s.set_default("max_oddsketch_len", limits.max_oddsketch_len as i64)?;
+        s.set_default("max_capacity", limits.max_capacity as i64)?;
         s.set_default("mining_threads", 1)?;
+        let mut default_data_dir = home_dir.clone();
+        default_data_dir.push(format!("{}/data", FOLDER_DIR));
+        s.set_default("data_dir", default_data_dir.to_str().unwrap())?;
+        s.set_default("storage", "disk")?;
+        let mut default_identity_key = home_dir.clone();
+        default_identity_key.push(format!("{}/identity_key", FOLDER_DIR));
+        s.set_default("identity_key", default_identity_key.to_str().unwrap())?;
+        s.set_default("noise", false)?;
+        let mut default_noise_key = home_dir.clone();
+        default_noise_key.push(format!("{}/noise_key", FOLDER_DIR));
+        s.set_default("noise_key", default_noise_key.to_str().unwrap())?;
 
         // Load config from file
         let mut default_config = home_dir;
@@ -79,6 +141,37 @@ impl Settings {
         if let Some(mining_threads) = matches.value_of("mining-threads") {
             s.set("mining_threads", mining_threads)?;
         }
-        s.try_into()
+        if let Some(data_dir) = matches.value_of("data-dir") {
+            s.set("data_dir", data_dir)?;
+        }
+        if let Some(storage) = matches.value_of("storage") {
+            s.set("storage", storage)?;
+        }
+        if let Some(identity_key) = matches.value_of("identity-key") {
+            s.set("identity_key", identity_key)?;
+        }
+        if matches.is_present("noise") {
+            s.set("noise", true)?;
+        }
+        if let Some(noise_key) = matches.value_of("noise-key") {
+            s.set("noise_key", noise_key)?;
+        }
+        let settings: Self = s.try_into()?;
+        if !settings.limits().supports_radius(settings.radius) {
+            return Err(ConfigError::Message(format!(
+                "radius {} exceeds max_oddsketch_len {} or max_capacity {}",
+                settings.radius, settings.max_oddsketch_len, settings.max_capacity
+            )));
+        }
+        Ok(settings)
+    }
+
+    /// The limits enforced on messages from peers.
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_oddsketch_len: self.max_oddsketch_len,
+            max_capacity: self.max_capacity,
+            ..Default::default()
+        }
     }
 }